@@ -0,0 +1,268 @@
+use crate::generic::Services;
+use crate::error::RyujinError;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use serde_json::Value;
+
+
+/// `Answers` holds the answers loaded from an answers file.
+///
+/// It contains the following fields:
+/// * `global`: A `HashMap<String, String>` of answers keyed by question variable, shared by every service.
+/// * `per_service`: A `HashMap<String, HashMap<String, String>>` of answers namespaced by service name, or by instance name for an instance (e.g. `site-a`).
+///
+/// A namespaced answer always takes precedence over a global answer for the same variable.
+#[derive(Debug, Default, Clone)]
+pub struct Answers {
+    pub global: HashMap<String, String>,
+    pub per_service: HashMap<String, HashMap<String, String>>,
+}
+
+impl Answers {
+    /// Returns the answer for a variable of a service, if the answers file provides one.
+    pub fn get(&self, service: &str, variable: &str) -> Option<&String> {
+        self.per_service
+            .get(service)
+            .and_then(|answers| answers.get(variable))
+            .or_else(|| self.global.get(variable))
+    }
+}
+
+/// Converts a scalar JSON value to the string stored in `Question.answer`.
+///
+/// Strings are returned as is, numbers and booleans are converted to their textual representation.
+/// Any other value (null, array, object) is rejected.
+fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Parses the content of an answers file into a JSON value.
+///
+/// The format is chosen from the extension of the file: `.json`, `.yaml`/`.yml` or `.toml`.
+///
+/// # Arguments
+///
+/// * `path` - The path of the answers file, used to pick the format.
+/// * `contents` - The content of the answers file.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if the extension is not supported or if the content cannot be parsed.
+fn parse_answers_file(path: &Path, contents: &str) -> Result<Value, RyujinError> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+
+    let invalid = |e: String| RyujinError::Input(format!("Unable to parse the answers file {}: {}", path.display(), e));
+    let value = match extension.as_str() {
+        "json" => serde_json::from_str(contents).map_err(|e| invalid(e.to_string()))?,
+        "yaml" | "yml" => serde_yaml::from_str(contents).map_err(|e| invalid(e.to_string()))?,
+        "toml" => toml::from_str::<toml::Value>(contents)
+            .map_err(|e| invalid(e.to_string()))
+            .and_then(|value| serde_json::to_value(value).map_err(|e| invalid(e.to_string())))?,
+        _ => return Err(RyujinError::Input(format!("Unsupported answers file format: {}. Use a .json, .yaml, .yml or .toml file.", path.display()))),
+    };
+
+    Ok(value)
+}
+
+/// Loads the answers from a JSON, YAML or TOML file.
+///
+/// The file must contain a map. Scalar entries are answers keyed by question variable and apply to every service.
+/// Map entries are answers namespaced by service name, or by instance name for an instance, for example:
+///
+/// ```yaml
+/// source_dir: ./www
+/// apache:
+///   apache_container_name: my-apache
+/// ```
+///
+/// # Arguments
+///
+/// * `path` - A string that represents the path of the answers file.
+///
+/// # Returns
+///
+/// * `Ok(Answers)` - The answers found in the file.
+/// * `Err(RyujinError)` - If the file cannot be read or parsed.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if the file cannot be read, if its format is not supported, if it is not a map,
+/// or if one of its answers is not a string, a number or a boolean.
+///
+/// # Example
+///
+/// ```
+/// let answers = load_answers("answers.yaml").unwrap();
+/// ```
+pub fn load_answers(path: &str) -> Result<Answers, RyujinError> {
+    let path = Path::new(path);
+    let contents = read_to_string(path)
+        .map_err(|e| RyujinError::Input(format!("Unable to read the answers file {}: {}", path.display(), e)))?;
+    let value = parse_answers_file(path, &contents)?;
+
+    let entries = value.as_object()
+        .ok_or_else(|| RyujinError::Input(format!("The answers file {} must contain a map of variables.", path.display())))?;
+
+    let mut answers = Answers::default();
+
+    for (key, value) in entries {
+        if let Value::Object(service_answers) = value {
+            let namespace = answers.per_service.entry(key.clone()).or_default();
+            for (variable, answer) in service_answers {
+                let answer = scalar_to_string(answer)
+                    .ok_or_else(|| RyujinError::Input(format!("The answer for {}.{} must be a string, a number or a boolean.", key, variable)))?;
+                namespace.insert(variable.clone(), answer);
+            }
+        } else {
+            let answer = scalar_to_string(value)
+                .ok_or_else(|| RyujinError::Input(format!("The answer for {} must be a string, a number or a boolean.", key)))?;
+            answers.global.insert(key.clone(), answer);
+        }
+    }
+
+    Ok(answers)
+}
+
+/// Fills the questions of the selected services with the answers of an answers file.
+///
+/// For each question that has no answer yet, the answer is looked up first in the namespace of the service,
+/// then in the global answers. Every answer of the file must be used, so that a misspelt service or variable is
+/// reported instead of silently falling back to the question or to its default.
+///
+/// # Arguments
+///
+/// * `services` - A mutable reference to the selected `Services`.
+/// * `answers` - A reference to the `Answers` loaded from the answers file.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` listing the namespaces that are not a selected service or instance,
+/// and the variables that are not a question of their service, or of any selected service for the global answers.
+///
+/// # Example
+///
+/// ```
+/// let answers = load_answers("answers.json").unwrap();
+/// apply_answers(&mut selected_services, &answers)?;
+/// ```
+pub fn apply_answers(services: &mut Services, answers: &Answers) -> Result<(), RyujinError> {
+    let mut unknown: Vec<String> = Vec::new();
+    for (name, namespace) in &answers.per_service {
+        match services.get(name) {
+            Some(service) => unknown.extend(namespace.keys()
+                .filter(|variable| !service.questions.iter().any(|question| &question.variable == *variable))
+                .map(|variable| format!("{}.{} is not a question of {}", name, variable, name))),
+            None => unknown.push(format!("{} is not a selected service", name)),
+        }
+    }
+    for variable in answers.global.keys() {
+        if !services.values().any(|service| service.questions.iter().any(|question| &question.variable == variable)) {
+            unknown.push(format!("{} is not a question of the selected services", variable));
+        }
+    }
+    if !unknown.is_empty() {
+        unknown.sort();
+        return Err(RyujinError::Input(format!("The answers file has unknown entries:\n{}", unknown.join("\n"))));
+    }
+
+    for (name, service) in services.iter_mut() {
+        for question in &mut service.questions {
+            if question.answer.is_none() {
+                question.answer = answers.get(name, &question.variable).cloned();
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    /// Writes answers files in a directory of their own and loads them.
+    fn load(files: &[(&str, &str)]) -> Vec<Result<Answers, RyujinError>> {
+        let dir = std::env::temp_dir().join(format!("ryujin-answers-{}-{}", files[0].0, std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let answers = files.iter().map(|(name, content)| {
+            write(dir.join(name), content).unwrap();
+            load_answers(&dir.join(name).to_string_lossy())
+        }).collect();
+        remove_dir_all(&dir).unwrap();
+        answers
+    }
+
+    fn catalog() -> Services {
+        let path = format!("{}/services/services.json", env!("CARGO_MANIFEST_DIR"));
+        serde_json::from_str(&read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn the_three_formats_give_the_same_answers() {
+        let loaded = load(&[
+            ("answers.json", r#"{ "source_dir": "./www", "apache": { "apache_container_name": "web", "port": 8080, "tls": true } }"#),
+            ("answers.yaml", "source_dir: ./www\napache:\n  apache_container_name: web\n  port: 8080\n  tls: true\n"),
+            ("answers.toml", "source_dir = \"./www\"\n[apache]\napache_container_name = \"web\"\nport = 8080\ntls = true\n"),
+        ]);
+        for answers in loaded {
+            let answers = answers.unwrap();
+            assert_eq!(answers.get("apache", "source_dir").map(String::as_str), Some("./www"));
+            assert_eq!(answers.get("apache", "apache_container_name").map(String::as_str), Some("web"));
+            assert_eq!(answers.get("apache", "port").map(String::as_str), Some("8080"));
+            assert_eq!(answers.get("apache", "tls").map(String::as_str), Some("true"));
+            assert_eq!(answers.get("nginx", "apache_container_name"), None);
+        }
+    }
+
+    #[test]
+    fn invalid_files_are_input_errors() {
+        let loaded = load(&[
+            ("answers.ini", "source_dir=./www"),
+            ("list.yaml", "- source_dir\n"),
+            ("nested.yaml", "apache:\n  volumes: [a, b]\n"),
+            ("broken.json", "{ \"source_dir\": "),
+        ]);
+        for answers in loaded {
+            assert!(matches!(answers, Err(RyujinError::Input(_))), "{:?}", answers);
+        }
+    }
+
+    #[test]
+    fn a_namespaced_answer_takes_precedence() {
+        let catalog = catalog();
+        let mut services: Services = [("site-a", "nginx"), ("site-b", "nginx")].iter()
+            .map(|(name, service)| (name.to_string(), catalog[*service].clone()))
+            .collect();
+        let mut answers = Answers::default();
+        answers.global.insert("nginx_content".to_string(), "./www".to_string());
+        answers.per_service.insert("site-b".to_string(), HashMap::from([("nginx_content".to_string(), "./site-b".to_string())]));
+
+        apply_answers(&mut services, &answers).unwrap();
+        let answer = |name: &str| services[name].questions.iter().find(|question| question.variable == "nginx_content").and_then(|question| question.answer.clone());
+        assert_eq!(answer("site-a").as_deref(), Some("./www"));
+        assert_eq!(answer("site-b").as_deref(), Some("./site-b"));
+    }
+
+    #[test]
+    fn unknown_entries_are_reported() {
+        let catalog = catalog();
+        let mut services: Services = HashMap::from([("nginx".to_string(), catalog["nginx"].clone())]);
+        let mut answers = Answers::default();
+        answers.global.insert("nginx_contnet".to_string(), "./www".to_string());
+        answers.per_service.insert("nginx".to_string(), HashMap::from([("certificate_pth".to_string(), "./a.crt".to_string())]));
+        answers.per_service.insert("apache".to_string(), HashMap::new());
+
+        let error = apply_answers(&mut services, &answers).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid input: The answers file has unknown entries:\n\
+            apache is not a selected service\n\
+            nginx.certificate_pth is not a question of nginx\n\
+            nginx_contnet is not a question of the selected services");
+        assert!(services["nginx"].questions.iter().all(|question| question.answer.is_none()));
+    }
+}
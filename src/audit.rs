@@ -0,0 +1,501 @@
+use crate::error::RyujinError;
+use crate::validate::{line_of, is_url};
+use crate::validator::{resolve_host_path, is_named_volume};
+use serde::Serialize;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+
+/// `Severity` is an enum that represents how risky a finding of the audit is, from `Low` to `Critical`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Severity::Low => write!(f, "low"),
+            Severity::Medium => write!(f, "medium"),
+            Severity::High => write!(f, "high"),
+            Severity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// `Finding` is a struct that represents a risky setting found by the audit.
+///
+/// It contains the following fields:
+/// * `severity`: A `Severity` that represents how risky the setting is.
+/// * `rule`: A `String` that identifies the check, e.g. `docker-socket`.
+/// * `service`: A `String` that represents the compose service of the setting, empty for the top-level settings.
+/// * `location`: A `String` that represents where the setting is, as `<file>:<line>`.
+/// * `message`: A `String` that describes the risk.
+/// * `remediation`: A `String` that explains how to fix it.
+#[derive(Serialize, Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    pub rule: String,
+    pub service: String,
+    pub location: String,
+    pub message: String,
+    pub remediation: String,
+}
+
+/// `AuditReport` is a struct that represents the result of the audit, as printed with `--format json`.
+///
+/// It contains the following fields:
+/// * `file`: A `String` that represents the path of the audited compose file.
+/// * `findings`: A `Vec<Finding>` that contains the findings, the most severe first.
+/// * `summary`: A `BTreeMap<String, usize>` that contains the number of findings of each severity.
+#[derive(Serialize, Debug, Clone)]
+pub struct AuditReport {
+    pub file: String,
+    pub findings: Vec<Finding>,
+    pub summary: BTreeMap<String, usize>,
+}
+
+/// Collects the findings of the audit of a compose file.
+struct Auditor<'a> {
+    content: &'a str,
+    file_name: &'a str,
+    base_dir: &'a Path,
+    findings: Vec<Finding>,
+}
+
+impl Auditor<'_> {
+    /// Adds a finding located at the value found at `path` in the compose file.
+    fn report(&mut self, severity: Severity, rule: &str, service: &str, path: &[String], message: String, remediation: &str) {
+        let location = format!("{}:{}", self.file_name, line_of(self.content, path));
+        self.findings.push(Finding {
+            severity,
+            rule: rule.to_string(),
+            service: service.to_string(),
+            location,
+            message,
+            remediation: remediation.to_string(),
+        });
+    }
+
+    /// Checks the bind mounts of a service: the Docker socket and the private keys readable by every user.
+    fn check_volumes(&mut self, name: &str, service: &Value) {
+        let Some(volumes) = service.get("volumes").and_then(Value::as_sequence) else { return };
+        for (index, volume) in volumes.iter().enumerate() {
+            // A volume is `source:target[:mode]` or a map with `type`, `source` and `target`
+            let source = match volume {
+                Value::String(volume) => volume.split(':').next().filter(|_| volume.contains(':')).map(str::to_string),
+                Value::Mapping(_) => volume.get("source").and_then(Value::as_str).map(str::to_string),
+                _ => None,
+            };
+            let Some(source) = source else { continue };
+            let path = vec!["services".to_string(), name.to_string(), "volumes".to_string(), index.to_string()];
+
+            if source.trim_end_matches('/').ends_with("/docker.sock") {
+                self.report(Severity::Critical, "docker-socket", name, &path,
+                    format!("{} mounts the Docker socket {}, which gives root access to the host.", name, source),
+                    "Remove the mount, or put a socket proxy that only allows the API calls needed (e.g. tecnativa/docker-socket-proxy) between the service and the socket.");
+            } else if !is_named_volume(&source) {
+                let key_path = resolve_host_path(&source, self.base_dir);
+                self.check_key_file(name, &path, &source, &key_path);
+            }
+        }
+    }
+
+    /// Reports a private key that every user of the host can read.
+    fn check_key_file(&mut self, name: &str, path: &[String], source: &str, key_path: &Path) {
+        let file_name = key_path.file_name().map(|file_name| file_name.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let looks_like_key = file_name.contains("key") || file_name.contains("private") || file_name.starts_with("id_");
+        if !looks_like_key || !key_path.is_file() {
+            return;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Ok(metadata) = key_path.metadata() {
+                if metadata.permissions().mode() & 0o004 != 0 {
+                    let service_name = if name.is_empty() { "the compose file" } else { name };
+                    self.report(Severity::High, "world-readable-key", name, path,
+                        format!("{} mounts the key file {}, which every user of the host can read.", service_name, source),
+                        "Restrict the permissions of the key file with chmod 600, and mount it as a Docker secret (see --docker-secrets).");
+                }
+            }
+        }
+    }
+
+    /// Checks the host ports of a service that are published on every interface.
+    fn check_ports(&mut self, name: &str, service: &Value) {
+        let Some(ports) = service.get("ports").and_then(Value::as_sequence) else { return };
+        for (index, port) in ports.iter().enumerate() {
+            let (public, text) = match port {
+                Value::Number(number) => (true, number.to_string()),
+                Value::String(port) => {
+                    // `[ip]:host:container`, `ip:host:container`, `host:container` or `container`
+                    let host_ip = if let Some(rest) = port.strip_prefix('[') {
+                        rest.split(']').next().map(str::to_string)
+                    } else if port.matches(':').count() >= 2 {
+                        port.split(':').next().map(str::to_string)
+                    } else {
+                        None
+                    };
+                    (host_ip.is_none_or(|ip| ip == "0.0.0.0" || ip == "::"), port.clone())
+                },
+                Value::Mapping(_) => {
+                    let host_ip = port.get("host_ip").and_then(Value::as_str);
+                    let target = port.get("target").map(|target| serde_yaml::to_string(target).unwrap_or_default().trim().to_string()).unwrap_or_default();
+                    (host_ip.is_none_or(|ip| ip == "0.0.0.0" || ip == "::"), format!("target {}", target))
+                },
+                _ => continue,
+            };
+            if public {
+                let path = vec!["services".to_string(), name.to_string(), "ports".to_string(), index.to_string()];
+                self.report(Severity::Medium, "public-port", name, &path,
+                    format!("{} publishes the port {} on every interface (0.0.0.0).", name, text),
+                    "Bind the port to the interface it is meant for, e.g. 127.0.0.1:8080:80 when only the host uses it, or put the service behind a reverse proxy.");
+            }
+        }
+    }
+
+    /// Reports an image without tag or with the `latest` tag.
+    fn check_image_tag(&mut self, name: &str, image: &str, path: &[String]) {
+        if image.contains('@') || image.contains("${") {
+            return;
+        }
+        let tag = image.rsplit('/').next().and_then(|last| last.split_once(':')).map(|(_, tag)| tag);
+        if tag.is_none() || tag == Some("latest") {
+            self.report(Severity::Low, "latest-tag", name, path,
+                format!("{} uses the image {}, whose latest tag changes without notice.", name, image),
+                "Pin the image to a version tag, or to a digest (image@sha256:...), so that the stack does not change on the next pull.");
+        }
+    }
+
+    /// Checks the images of a service, and the `FROM` of its Dockerfile when it is built from a local directory.
+    fn check_images(&mut self, name: &str, service: &Value) {
+        if let Some(image) = service.get("image").and_then(Value::as_str) {
+            let path = vec!["services".to_string(), name.to_string(), "image".to_string()];
+            self.check_image_tag(name, image, &path);
+        }
+
+        let (context, dockerfile) = match service.get("build") {
+            Some(Value::String(context)) => (context.clone(), "Dockerfile".to_string()),
+            Some(build) => (
+                build.get("context").and_then(Value::as_str).unwrap_or(".").to_string(),
+                build.get("dockerfile").and_then(Value::as_str).unwrap_or("Dockerfile").to_string(),
+            ),
+            None => return,
+        };
+        if is_url(&context) {
+            return;
+        }
+        let dockerfile_path = resolve_host_path(&context, self.base_dir).join(&dockerfile);
+        let Ok(content) = read_to_string(&dockerfile_path) else { return };
+
+        let mut stages: Vec<String> = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().filter(|word| !word.starts_with("--")).collect();
+            if words.first().map(|word| word.to_uppercase()) != Some("FROM".to_string()) || words.len() < 2 {
+                continue;
+            }
+            let image = words[1];
+            // A FROM can start from a previous stage of the Dockerfile, which is not an image
+            let is_stage = stages.iter().any(|stage| stage == image);
+            if words.len() >= 4 && words[2].eq_ignore_ascii_case("as") {
+                stages.push(words[3].to_string());
+            }
+            if image == "scratch" || image.contains('$') || is_stage {
+                continue;
+            }
+            let found = self.findings.len();
+            self.check_image_tag(name, image, &["services".to_string(), name.to_string(), "build".to_string()]);
+            // Point at the FROM line of the Dockerfile rather than at the build key
+            for finding in &mut self.findings[found..] {
+                finding.location = format!("{}:{}", dockerfile_path.display(), index + 1);
+            }
+        }
+    }
+}
+
+/// Audits the content of a `docker-compose.yml` file for risky settings.
+///
+/// The following checks are run on every service:
+/// * `docker-socket` (critical): the Docker socket is mounted;
+/// * `privileged` (critical): the container is privileged;
+/// * `host-network` (high): the container uses the network of the host;
+/// * `world-readable-key` (high): a key file mounted from the host, or used as a secret, can be read by every user;
+/// * `no-new-privileges` (medium): `security_opt` does not contain `no-new-privileges:true`;
+/// * `public-port` (medium): a port is published on every interface;
+/// * `latest-tag` (low): an image, or the `FROM` of a local Dockerfile, has no tag or the `latest` tag.
+///
+/// # Arguments
+///
+/// * `content` - The content of the compose file.
+/// * `file_name` - The name of the file, used in the location of the findings.
+/// * `base_dir` - The directory of the compose file, the relative paths are relative to it.
+///
+/// # Returns
+///
+/// * `Ok(Vec<Finding>)` - The findings, the most severe first, then in the order of the file.
+/// * `Err(RyujinError)` - If the content is not a compose file.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Findings` if the content is not valid YAML.
+///
+/// # Example
+///
+/// ```
+/// let findings = audit_compose("services:\n  web:\n    privileged: true\n", "docker-compose.yml", Path::new(".")).unwrap();
+/// ```
+pub fn audit_compose(content: &str, file_name: &str, base_dir: &Path) -> Result<Vec<Finding>, RyujinError> {
+    let document: Value = serde_yaml::from_str(content)
+        .map_err(|e| RyujinError::Findings(format!("{} is not a valid compose file: {}", file_name, e)))?;
+
+    let mut auditor = Auditor { content, file_name, base_dir, findings: Vec::new() };
+
+    if let Some(services) = document.get("services").and_then(Value::as_mapping) {
+        for (name, service) in services {
+            let Some(name) = name.as_str() else { continue };
+            let service_path = |key: &str| vec!["services".to_string(), name.to_string(), key.to_string()];
+
+            if service.get("privileged").and_then(Value::as_bool) == Some(true) {
+                auditor.report(Severity::Critical, "privileged", name, &service_path("privileged"),
+                    format!("{} runs privileged, with every capability and access to the devices of the host.", name),
+                    "Remove privileged: true and add only the capabilities the service needs with cap_add.");
+            }
+            if service.get("network_mode").and_then(Value::as_str) == Some("host") {
+                auditor.report(Severity::High, "host-network", name, &service_path("network_mode"),
+                    format!("{} uses the network of the host, so it bypasses the isolation of the compose networks.", name),
+                    "Remove network_mode: host, attach the service to a compose network and publish only the ports it needs.");
+            }
+
+            let no_new_privileges = service.get("security_opt").and_then(Value::as_sequence)
+                .is_some_and(|options| options.iter().any(|option| matches!(option.as_str(), Some("no-new-privileges:true" | "no-new-privileges"))));
+            if !no_new_privileges {
+                auditor.report(Severity::Medium, "no-new-privileges", name, &["services".to_string(), name.to_string()],
+                    format!("{} does not set no-new-privileges, so its processes can gain privileges through setuid binaries.", name),
+                    "Add security_opt: [no-new-privileges:true] to the service (see --security).");
+            }
+
+            auditor.check_volumes(name, service);
+            auditor.check_ports(name, service);
+            auditor.check_images(name, service);
+        }
+    }
+
+    if let Some(secrets) = document.get("secrets").and_then(Value::as_mapping) {
+        for (name, secret) in secrets {
+            let (Some(name), Some(file)) = (name.as_str(), secret.get("file").and_then(Value::as_str)) else { continue };
+            let path = vec!["secrets".to_string(), name.to_string(), "file".to_string()];
+            auditor.check_key_file("", &path, file, &resolve_host_path(file, base_dir));
+        }
+    }
+
+    let mut findings = auditor.findings;
+    // Stable sort: the findings of the same severity stay in the order of the file
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+    Ok(findings)
+}
+
+/// Audits the `docker-compose.yml` file of a directory and prints the findings.
+///
+/// # Arguments
+///
+/// * `path` - The directory that contains the `docker-compose.yml` file, or the path of the file itself.
+/// * `json` - A `bool` that indicates whether the report is printed as JSON (see `AuditReport`) instead of text.
+/// * `fail_on` - The `Severity` from which a finding makes the audit fail, or `None` to never fail on findings.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Io` if the file cannot be read, and a `RyujinError::Findings` if the file
+/// is not valid YAML or if a finding is at least as severe as `fail_on`.
+///
+/// # Example
+///
+/// ```
+/// audit::handle("./output", true, Some(Severity::High)).unwrap();
+/// ```
+pub fn handle(path: &str, json: bool, fail_on: Option<Severity>) -> Result<(), RyujinError> {
+    let path = Path::new(path);
+    let file_path = if path.is_dir() { path.join("docker-compose.yml") } else { path.to_path_buf() };
+    let content = read_to_string(&file_path)
+        .map_err(|e| RyujinError::Io(std::io::Error::new(e.kind(), format!("Unable to read {}: {}", file_path.display(), e))))?;
+
+    let file_name = file_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let base_dir = file_path.parent().unwrap_or(Path::new("."));
+    let findings = audit_compose(&content, &file_name, base_dir)?;
+
+    let severities = [Severity::Critical, Severity::High, Severity::Medium, Severity::Low];
+    let mut summary: BTreeMap<String, usize> = severities.iter().map(|severity| (severity.to_string(), 0)).collect();
+    for finding in &findings {
+        *summary.entry(finding.severity.to_string()).or_default() += 1;
+    }
+
+    if json {
+        let report = AuditReport { file: file_path.display().to_string(), findings: findings.clone(), summary };
+        let output = serde_json::to_string_pretty(&report)
+            .map_err(|e| RyujinError::Io(std::io::Error::other(format!("Unable to serialize the audit report: {}", e))))?;
+        println!("{}", output);
+    } else {
+        for finding in &findings {
+            println!("{}: {}: {}", finding.severity, finding.location, finding.message);
+            println!("  fix: {}", finding.remediation);
+        }
+        let counts: Vec<String> = severities.iter().map(|severity| format!("{} {}", summary[&severity.to_string()], severity)).collect();
+        println!("{}: {} finding(s) ({}).", file_path.display(), findings.len(), counts.join(", "));
+    }
+
+    let failing = findings.iter().filter(|finding| fail_on.is_some_and(|fail_on| finding.severity >= fail_on)).count();
+    if failing > 0 {
+        let fail_on = fail_on.map(|severity| severity.to_string()).unwrap_or_default();
+        return Err(RyujinError::Findings(format!("{} finding(s) of severity {} or higher in {}.", failing, fail_on, file_path.display())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::path::PathBuf;
+
+    /// Creates an empty directory of its own for a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ryujin-audit-{}-{}", name, std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Audits a compose file that does not refer to any file of the host.
+    fn audit(content: &str) -> Vec<Finding> {
+        audit_compose(content, "docker-compose.yml", Path::new("/nonexistent")).unwrap()
+    }
+
+    /// The rules found, without the `no-new-privileges` finding that every service without `security_opt` gets.
+    fn rules(findings: &[Finding]) -> Vec<(&str, Severity, &str)> {
+        findings.iter()
+            .filter(|finding| finding.rule != "no-new-privileges")
+            .map(|finding| (finding.rule.as_str(), finding.severity, finding.location.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn a_hardened_service_has_no_finding() {
+        let findings = audit("services:\n  web:\n    image: nginx:1.27\n    ports:\n      - 127.0.0.1:8080:80\n    security_opt:\n      - no-new-privileges:true\n");
+        assert!(findings.is_empty(), "{:?}", findings);
+    }
+
+    #[test]
+    fn the_docker_socket_is_critical() {
+        let findings = audit("services:\n  web:\n    image: nginx:1.27\n    volumes:\n      - ./data:/data\n      - /var/run/docker.sock:/var/run/docker.sock\n");
+        assert_eq!(rules(&findings), vec![("docker-socket", Severity::Critical, "docker-compose.yml:6")]);
+    }
+
+    #[test]
+    fn privileged_and_host_network_are_reported() {
+        let findings = audit("services:\n  web:\n    image: nginx:1.27\n    network_mode: host\n    privileged: true\n");
+        assert_eq!(rules(&findings), vec![
+            ("privileged", Severity::Critical, "docker-compose.yml:5"),
+            ("host-network", Severity::High, "docker-compose.yml:4"),
+        ]);
+    }
+
+    #[test]
+    fn a_missing_no_new_privileges_is_reported() {
+        let findings = audit("services:\n  web:\n    image: nginx:1.27\n    security_opt:\n      - label:disable\n");
+        assert_eq!(findings.len(), 1);
+        assert_eq!((findings[0].rule.as_str(), findings[0].severity), ("no-new-privileges", Severity::Medium));
+    }
+
+    #[test]
+    fn only_ports_on_every_interface_are_public() {
+        let findings = audit("services:\n  web:\n    image: nginx:1.27\n    ports:\n      - 8080:80\n      - 127.0.0.1:8443:443\n      - 0.0.0.0:9000:9000\n      - \"[::1]:9001:9001\"\n      - target: 9002\n        host_ip: 127.0.0.1\n      - target: 9003\n");
+        assert_eq!(rules(&findings), vec![
+            ("public-port", Severity::Medium, "docker-compose.yml:5"),
+            ("public-port", Severity::Medium, "docker-compose.yml:7"),
+            ("public-port", Severity::Medium, "docker-compose.yml:11"),
+        ]);
+    }
+
+    #[test]
+    fn images_without_a_pinned_tag_are_reported() {
+        let findings = audit("services:\n  a:\n    image: nginx\n  b:\n    image: nginx:latest\n  c:\n    image: registry:5000/nginx\n  d:\n    image: nginx@sha256:0123\n  e:\n    image: ${IMAGE}\n  f:\n    image: registry:5000/nginx:1.27\n");
+        assert_eq!(rules(&findings), vec![
+            ("latest-tag", Severity::Low, "docker-compose.yml:3"),
+            ("latest-tag", Severity::Low, "docker-compose.yml:5"),
+            ("latest-tag", Severity::Low, "docker-compose.yml:7"),
+        ]);
+    }
+
+    #[test]
+    fn the_from_of_a_local_dockerfile_is_checked() {
+        let dir = test_dir("from");
+        create_dir_all(dir.join("app")).unwrap();
+        write(dir.join("app/Dockerfile"), "FROM node:20 AS build\nFROM --platform=linux/amd64 build\nFROM nginx\nFROM scratch\n").unwrap();
+        let findings = audit_compose("services:\n  web:\n    build: ./app\n", "docker-compose.yml", &dir).unwrap();
+        remove_dir_all(&dir).unwrap();
+
+        assert_eq!(rules(&findings), vec![("latest-tag", Severity::Low, format!("{}:3", dir.join("./app").join("Dockerfile").display()).as_str())]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_world_readable_key_is_reported() {
+        use std::fs::{set_permissions, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("key");
+        write(dir.join("server.key"), "key").unwrap();
+        write(dir.join("private.pem"), "key").unwrap();
+        write(dir.join("site.crt"), "certificate").unwrap();
+        set_permissions(dir.join("server.key"), Permissions::from_mode(0o644)).unwrap();
+        set_permissions(dir.join("private.pem"), Permissions::from_mode(0o600)).unwrap();
+        set_permissions(dir.join("site.crt"), Permissions::from_mode(0o644)).unwrap();
+        let content = "services:\n  web:\n    image: nginx:1.27\n    volumes:\n      - ./server.key:/key\n      - ./private.pem:/pem\n      - ./site.crt:/crt\nsecrets:\n  key:\n    file: ./server.key\n";
+        let findings = audit_compose(content, "docker-compose.yml", &dir).unwrap();
+        remove_dir_all(&dir).unwrap();
+
+        assert_eq!(rules(&findings), vec![
+            ("world-readable-key", Severity::High, "docker-compose.yml:5"),
+            ("world-readable-key", Severity::High, "docker-compose.yml:10"),
+        ]);
+    }
+
+    #[test]
+    fn invalid_yaml_is_a_finding() {
+        let error = audit_compose("services: [", "docker-compose.yml", Path::new(".")).unwrap_err();
+        assert!(matches!(error, RyujinError::Findings(_)));
+    }
+
+    #[test]
+    fn fail_on_is_a_severity_threshold() {
+        let dir = test_dir("fail-on");
+        // One high finding (host-network) and one medium finding (no-new-privileges)
+        write(dir.join("docker-compose.yml"), "services:\n  web:\n    image: nginx:1.27\n    network_mode: host\n").unwrap();
+        let path = dir.to_string_lossy().to_string();
+
+        let results = [
+            handle(&path, false, None),
+            handle(&path, false, Some(Severity::Critical)),
+            handle(&path, true, Some(Severity::High)),
+            handle(&path, false, Some(Severity::Low)),
+        ];
+        remove_dir_all(&dir).unwrap();
+
+        assert!(results[0].is_ok());
+        assert!(results[1].is_ok());
+        for result in &results[2..] {
+            let error = result.as_ref().unwrap_err();
+            assert!(matches!(error, RyujinError::Findings(_)), "{}", error);
+            assert_eq!(error.exit_code(), 7);
+        }
+        assert!(results[2].as_ref().unwrap_err().to_string().starts_with("Check failed: 1 finding(s) of severity high"));
+        assert!(results[3].as_ref().unwrap_err().to_string().starts_with("Check failed: 2 finding(s) of severity low"));
+    }
+}
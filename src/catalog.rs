@@ -1,5 +1,5 @@
 use crate::generic::Services;
-use std::io::{Error, ErrorKind};
+use crate::error::RyujinError;
 
 
 /// Prints the catalog of services.
@@ -83,7 +83,7 @@ fn print_detailed_catalog(services: Services){
 ///     Err(e) => println!("Error filtering catalog: {}", e),
 /// }
 /// ```
-fn filter_catalog_by_tags(services: &mut Services, tags: Vec<String>) -> Result<(), Error> {
+fn filter_catalog_by_tags(services: &mut Services, tags: Vec<String>) -> Result<(), RyujinError> {
     // remove services from services that do not have any of the tags
     let mut services_to_remove = Vec::new();
     for service in services.values(){
@@ -105,7 +105,7 @@ fn filter_catalog_by_tags(services: &mut Services, tags: Vec<String>) -> Result<
 
     // if no services are left, return an error
     if services.is_empty() {
-        return Err(Error::new(ErrorKind::InvalidInput, "No services found with the specified tags."));
+        return Err(RyujinError::Input("No services found with the specified tags.".to_string()));
     }
 
     Ok(())
@@ -134,7 +134,7 @@ fn filter_catalog_by_tags(services: &mut Services, tags: Vec<String>) -> Result<
 ///     Err(e) => println!("Error filtering catalog: {}", e),
 /// }
 /// ```
-fn filter_catalog_by_name(services: &mut Services, name: String) -> Result<(), Error> {
+fn filter_catalog_by_name(services: &mut Services, name: String) -> Result<(), RyujinError> {
     // remove services from services that do not contain the name string in their name
     let mut services_to_remove = Vec::new();
     for service in services.values(){
@@ -149,7 +149,7 @@ fn filter_catalog_by_name(services: &mut Services, name: String) -> Result<(), E
 
     // if no services are left, return an error
     if services.is_empty() {
-        return Err(Error::new(ErrorKind::InvalidInput, "No services found containing the specified name."));
+        return Err(RyujinError::Input("No services found containing the specified name.".to_string()));
     }
 
     Ok(())
@@ -181,7 +181,7 @@ fn filter_catalog_by_name(services: &mut Services, name: String) -> Result<(), E
 /// let name = Some("service1".to_string());
 /// catalog(&mut services, detailed, tags, name);
 /// ```
-pub fn handle(services : &mut Services, detailed: bool, tags: Option<Vec<String>>, name: Option<String>) -> Result<(), Error> {
+pub fn handle(services : &mut Services, detailed: bool, tags: Option<Vec<String>>, name: Option<String>) -> Result<(), RyujinError> {
     // filter the services based on the tags
     if let Some(tags) = tags {
         filter_catalog_by_tags(services, tags)?;
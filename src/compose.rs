@@ -1,16 +1,32 @@
 
-use crate::generic::{Services, Question, ReadmePartial, get_selected_services};
-use std::io::{Error, Write};
-use std::path::Path;
-use std::fs::{File, create_dir_all, remove_file, read_to_string};
+use crate::generic::{Services, Service, Question, QuestionKind, ReadmePartial, ResourceLimits, Size, get_selected_services, ryujin_dir};
+use crate::error::RyujinError;
+use crate::answers::{load_answers, apply_answers};
+use crate::validator::{validate_answer, describe_expected, is_named_volume};
+use crate::compose_model::{ComposeFile, ComposeVolume, ComposeHealthcheck, DeployLimits};
+use crate::validate::validate_compose;
+use crate::ports::{PortRemap, host_ports, apply_remaps, resolve_conflicts};
+use crate::healthcheck::{HealthcheckTuning, configure_healthchecks};
+use crate::dependencies::dependencies_of;
+use crate::profiles::can_depend_on;
+use crate::instances::{is_instance, namespaced};
+use crate::merge::{merge_compose, confirm_merge};
+use crate::output::Output;
+use crate::resources::{LimitOverride, resolve_limits};
+use crate::security::{SecurityLevel, hardening_for};
+use crate::certificates::{CertificateOptions, generate_certificate};
+use crate::env_file::{EnvEntry, env_variable_name, interpolation, generate_secret, unique_entries, write_env_file};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::fs::{canonicalize, create_dir_all, remove_file, read_to_string};
 use tera::{Tera, Context};
-use std::env;
 
 
 /// Reads a line of input from the user.
 ///
-/// This function reads a line of input from the standard input (usually the console). It trims the trailing newline
-/// and returns the input as a `String`.
+/// This function reads a line of input from the standard input (usually the console) and returns the input as a `String`.
+/// Reaching the end of the input is reported as an error, so a closed stdin never loops on an empty answer.
 ///
 /// # Returns
 ///
@@ -19,7 +35,7 @@ use std::env;
 ///
 /// # Errors
 ///
-/// This function will return an error if an I/O error occurs while reading the input.
+/// This function will return an error if an I/O error occurs while reading the input or if the end of the input is reached.
 ///
 /// # Example
 ///
@@ -29,55 +45,267 @@ use std::env;
 fn get_input() -> Result<String, Error> {
     let mut input = String::new();
     match std::io::stdin().read_line(&mut input) {
+        Ok(0) => Err(Error::new(ErrorKind::UnexpectedEof, "The standard input was closed while waiting for an answer.")),
         Ok(_) => Ok(input),
         Err(e) => Err(e),
     }
 }
 
 
+/// Reads a secret from the terminal without echoing it.
+///
+/// # Errors
+///
+/// This function will return an error if the terminal cannot be read.
+fn read_secret() -> Result<String, Error> {
+    rpassword::read_password()
+}
+
+/// Inserts the answer of a question in a Tera context.
+///
+/// The answers of `bool` questions are inserted as booleans so that `{% if variable %}` behaves as expected,
+/// every other answer is inserted as a string.
+///
+/// # Arguments
+///
+/// * `context` - A mutable reference to the Tera `Context`.
+/// * `question` - A reference to the `Question` the answer belongs to.
+/// * `answer` - The answer to insert.
+fn insert_answer(context: &mut Context, question: &Question, answer: &str) {
+    match question.kind {
+        QuestionKind::Bool => context.insert(&question.variable, &(answer == "true")),
+        _ => context.insert(&question.variable, answer),
+    }
+}
+
+/// Evaluates the `when` condition of a question.
+///
+/// The condition is a Tera expression over the answers collected so far, for example `package_manager == "npm"`.
+/// A question without condition is always asked.
+///
+/// # Arguments
+///
+/// * `question` - A reference to the `Question` whose condition is evaluated.
+/// * `context` - The Tera `Context` that holds the answers collected so far.
+///
+/// # Returns
+///
+/// * `Ok(bool)` - `true` if the question must be asked, `false` if it must be skipped.
+/// * `Err(RyujinError)` - If the condition cannot be evaluated.
+///
+/// # Errors
+///
+/// This function will return an error if the condition is not a valid Tera expression.
+fn condition_holds(question: &Question, context: &Context) -> Result<bool, RyujinError> {
+    match &question.when {
+        Some(condition) => Tera::one_off(&format!("{{% if {} %}}true{{% endif %}}", condition), context, false)
+            .map(|rendered| rendered == "true")
+            .map_err(|e| RyujinError::Catalog(format!("Unable to evaluate the condition of {}: {}", question.variable, RyujinError::from(e)))),
+        None => Ok(true),
+    }
+}
+
+/// Computes the default answer of a question.
+///
+/// The `default` of a question is either a literal or a Tera expression over the answers collected so far,
+/// for example `{{ project_name }}-nginx`. It is rendered with the provided context.
+///
+/// # Arguments
+///
+/// * `question` - A reference to the `Question` whose default is computed.
+/// * `context` - The Tera `Context` that holds the project name and the answers collected so far.
+///
+/// # Returns
+///
+/// * `Ok(Some(String))` - The rendered default answer.
+/// * `Ok(None)` - If the question has no default.
+/// * `Err(RyujinError)` - If the default expression cannot be rendered.
+///
+/// # Errors
+///
+/// This function will return an error if the default expression is not a valid Tera template or references an unknown variable.
+fn render_default(question: &Question, context: &Context) -> Result<Option<String>, RyujinError> {
+    match &question.default {
+        Some(default) => Tera::one_off(default, context, false)
+            .map(|rendered| Some(rendered.trim().to_string()))
+            .map_err(|e| RyujinError::Catalog(format!("Unable to compute the default of {}: {}", question.variable, RyujinError::from(e)))),
+        None => Ok(None),
+    }
+}
+
+/// Validates the path of a generated certificate or key as the answer of a question.
+///
+/// The files are only rendered in a dry run, so their path is used as is instead of being checked on the disk.
+fn validate_generated(question: &Question, path: &str, base_dir: &Path, output: &Output) -> Result<String, String> {
+    if output.dry_run {
+        return Ok(path.to_string());
+    }
+    validate_answer(question, path, base_dir)
+}
+
 /// Asks the user questions related to each service.
 ///
-/// This function iterates over each service in the provided `Services` object, and for each service, it iterates over
-/// each question. It asks the user each question by calling the `ask_question()` function and passing the `get_input()`
-/// function as the input provider.
+/// This function iterates over each service in the provided `Services` object, in alphabetical order, and for each service, it iterates over
+/// each question in order. Questions whose `when` condition is false are marked as skipped and lose their answer.
+/// Answers already given (for instance by an answers file) are validated with the validator of the question's kind.
+/// The other questions are asked by calling the `ask_question()` function and passing the `get_input()` function as the
+/// input provider.
+///
+/// The default of each question is computed from the project name and the answers collected so far for the service.
+/// When `use_defaults` is `true` (an answers file is used) or when the standard input is not a terminal, the default
+/// is used without asking. An invalid answer of the answers file is never replaced by the default: the question is
+/// asked again, with the default as suggestion. Secret questions are read without echo, and a random value is generated
+/// when they are left empty. When the standard input is not a terminal, no question is asked: secrets without answer
+/// are generated and the function fails with the list of the invalid answers and of the unanswered variables instead.
+///
+/// When the certificate question of a service that serves TLS (see `Service.tls`) is left empty, a certificate and its
+/// key are generated in the output directory and both questions are answered with their paths. This is done without
+/// asking with `--self-signed`, even when the standard input is not a terminal.
 ///
 /// # Arguments
 ///
 /// * `services` - A mutable reference to a `Services` object that contains the services for which the user will be asked questions.
+/// * `base_dir` - The directory relative host paths are resolved against.
+/// * `project_name` - The name of the project, available as `project_name` in the default expressions.
+/// * `use_defaults` - A `bool` that indicates whether the defaults are used without asking.
+/// * `certificates` - A reference to the `CertificateOptions` of the generated certificates.
+/// * `output` - A mutable reference to the `Output` the generated certificates are written to.
 ///
 /// # Returns
 ///
-/// * `Ok(())` - If all questions were asked successfully.
-/// * `Err(Error)` - An `Error` object that indicates an I/O error occurred while reading the input.
+/// * `Ok(())` - If all questions were answered or skipped.
+/// * `Err(RyujinError)` - If the input cannot be read, or if some answers are invalid or missing.
 ///
 /// # Errors
 ///
-/// This function will return an error if an I/O error occurs while reading the input for any question.
+/// This function will return a `RyujinError::Io` if an I/O error occurs while reading the input for any question, a
+/// `RyujinError::Catalog` if a default or a condition cannot be computed, or a `RyujinError::Input` if the standard input
+/// is not a terminal and some answers are invalid or missing, or if a certificate cannot be generated.
 ///
 /// # Example
 ///
 /// ```
 /// let mut services = load_services();
-/// ask_services_questions(&mut services).unwrap();
+/// ask_services_questions(&mut services, Path::new("./output"), "output", false, &CertificateOptions::default(), &mut Output::default()).unwrap();
 /// ```
-fn ask_services_questions(services: &mut Services) -> Result<(), Error>  {
-    for service in services.values_mut(){
+fn ask_services_questions(services: &mut Services, base_dir: &Path, project_name: &str, use_defaults: bool, certificates: &CertificateOptions, output: &mut Output) -> Result<(), RyujinError>  {
+    let interactive = std::io::stdin().is_terminal();
+    let mut unanswered: Vec<String> = Vec::new();
+    let mut invalid: Vec<String> = Vec::new();
+
+    // The services are asked in alphabetical order, so the questions come in the same order on every run
+    let mut names: Vec<String> = services.keys().cloned().collect();
+    names.sort();
+
+    for name in &names {
+        let service = services.get_mut(name).expect("The name comes from the services");
+        // The answers collected so far for the service are available to the conditions and the default expressions
+        let mut context = Context::new();
+        context.insert("project_name", project_name);
+        context.insert("service", name);
+
+        // The answers of the key questions, once their certificate is generated
+        let tls = service.tls.clone();
+        let mut generated: HashMap<String, String> = HashMap::new();
+        let mut key_group: Option<u32> = None;
+
         for question in &mut service.questions {
-            ask_question(question, get_input)?;
+            if !condition_holds(question, &context)? {
+                question.skipped = true;
+                question.answer = None;
+                continue;
+            }
+
+            if let Some(key) = generated.remove(&question.variable) {
+                let key = validate_generated(question, &key, base_dir, output)
+                    .map_err(|reason| RyujinError::Input(format!("The generated key of {} cannot answer {}: {}", name, question.variable, reason)))?;
+                insert_answer(&mut context, question, &key);
+                question.answer = Some(key);
+                continue;
+            }
+
+            // An invalid answer is asked again rather than replaced by the default
+            let mut rejected = false;
+            if let Some(given) = question.answer.take() {
+                match validate_answer(question, &given, base_dir) {
+                    Ok(normalized) => question.answer = Some(normalized),
+                    Err(reason) if interactive => {
+                        output.notice(&format!("The given answer for {}.{} is invalid: {}\n", name, question.variable, reason));
+                        rejected = true;
+                    },
+                    Err(reason) => {
+                        invalid.push(format!("{}.{}: {}", name, question.variable, reason));
+                        continue;
+                    },
+                }
+            }
+
+            // A certificate question left empty can be answered with a generated certificate
+            let is_certificate = tls.as_ref().is_some_and(|tls| tls.certificate == question.variable);
+            if is_certificate && question.answer.is_none() && question.default.is_none() && (certificates.self_signed || interactive) {
+                let generate = certificates.self_signed || ask_certificate_question(question, base_dir, output, get_input)?;
+                if generate {
+                    let files = generate_certificate(output, base_dir, name, certificates)?;
+                    output.notice(&format!("A certificate and its key were generated for {}: {} and {}.\n", name, files.certificate, files.key));
+                    let certificate = validate_generated(question, &files.certificate, base_dir, output)
+                        .map_err(|reason| RyujinError::Input(format!("The generated certificate of {} cannot answer {}: {}", name, question.variable, reason)))?;
+                    question.answer = Some(certificate);
+                    if let Some(tls) = &tls {
+                        generated.insert(tls.key.clone(), files.key);
+                    }
+                    key_group = files.group;
+                }
+            }
+
+            if question.answer.is_none() {
+                let default = render_default(question, &context)?;
+                match default {
+                    Some(default) if (use_defaults && !rejected) || !interactive => {
+                        let answer = validate_answer(question, &default, base_dir)
+                            .map_err(|reason| RyujinError::Catalog(format!("The default of {}.{} is invalid: {}", name, question.variable, reason)))?;
+                        question.answer = Some(answer);
+                    },
+                    None if question.secret && !interactive => question.answer = Some(generate_secret()),
+                    _ if !interactive => {
+                        unanswered.push(format!("{}.{}", name, question.variable));
+                        continue;
+                    },
+                    default if question.secret => ask_question(question, default.as_deref(), base_dir, output, read_secret)?,
+                    default => ask_question(question, default.as_deref(), base_dir, output, get_input)?,
+                }
+            }
+
+            if let Some(answer) = &question.answer {
+                insert_answer(&mut context, question, answer);
+            }
         }
+        service.key_group = key_group;
+    }
+
+    if !invalid.is_empty() {
+        invalid.sort();
+        return Err(RyujinError::Input(format!("The following answers are invalid:\n{}", invalid.join("\n"))));
+    }
+    if !unanswered.is_empty() {
+        unanswered.sort();
+        return Err(RyujinError::Input(format!("The standard input is not a terminal and the following variables have no answer: {}. Add them to the answers file.", unanswered.join(", "))));
     }
     Ok(())
 }
 
 /// Asks a question and stores the answer.
 ///
-/// This function takes a mutable reference to a `Question` object and a function to get user input. It prints the question,
-/// gets the user input, sanitizes it by removing non-alphanumeric characters (except for whitespace, underscores, periods, and slashes),
-/// truncates it if it's longer than 50 characters, and stores it in the `answer` field of the `Question` object.
+/// This function takes a mutable reference to a `Question` object and a function to get user input. It prints the question
+/// followed by the kind of answer expected and the default answer, gets the user input and validates it with the validator
+/// of the question's kind. An empty input selects the default answer, or a random value for a secret without default. As long as the answer is not valid, the reason is
+/// explained and the question is asked again. The normalized answer is stored in the `answer` field of the `Question` object.
 ///
 /// # Arguments
 ///
 /// * `question` - A mutable reference to a `Question` object where the question is stored and the answer will be stored.
+/// * `default` - The default answer, used when the user just presses Enter.
+/// * `base_dir` - The directory relative host paths are resolved against.
+/// * `output` - A reference to the `Output`, which prints the question on the standard error with `--stdout`.
 /// * `get_input` - A function that gets user input and returns a `Result<String, Error>`.
 ///
 /// # Returns
@@ -93,26 +321,119 @@ fn ask_services_questions(services: &mut Services) -> Result<(), Error>  {
 ///
 /// ```
 /// let mut question = Question::new("What is your name?");
-/// ask_question(&mut question, get_input).unwrap();
+/// ask_question(&mut question, Some("ryujin"), Path::new("."), &Output::default(), get_input).unwrap();
 /// ```
-fn ask_question<F: FnMut() -> Result<String, Error>>(question: &mut Question, mut get_input: F) -> Result<(), Error>  {
-    let max_length = 50;
+fn ask_question<F: FnMut() -> Result<String, Error>>(question: &mut Question, default: Option<&str>, base_dir: &Path, output: &Output, mut get_input: F) -> Result<(), Error>  {
+    loop {
+        match default {
+            // The default of a secret is never displayed
+            Some(_) if question.secret => output.notice(&format!("{} {} (press Enter to keep the default value)", question.question, describe_expected(question))),
+            Some(default) => output.notice(&format!("{} {} (default: {})", question.question, describe_expected(question), default)),
+            None if question.secret => output.notice(&format!("{} {} (press Enter to generate a random value)", question.question, describe_expected(question))),
+            None => output.notice(&format!("{} {}", question.question, describe_expected(question))),
+        }
+        let input = match get_input() {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Error reading input: {}", e);
+                return Err(e);
+            },
+        };
+        let answer = match (input.trim().is_empty(), default) {
+            (true, Some(default)) => default.to_string(),
+            (true, None) if question.secret => generate_secret(),
+            _ => input,
+        };
 
-    println!("{}", question.question);
-    let answer = match get_input() {
-        Ok(input) => input,
-        Err(e) => {
-            eprintln!("Error reading input: {}", e);
-            return Err(e.into());
-        },
-    };    
-    let mut sanitized_answer = answer.trim().to_string().replace(|c: char| !c.is_alphanumeric() && !c.is_whitespace() && c != '_' && c != '.' && c!= '/', "");
-    if sanitized_answer.len() > max_length {
-        sanitized_answer.truncate(max_length);
+        match validate_answer(question, &answer, base_dir) {
+            Ok(normalized) => {
+                question.answer = Some(normalized);
+                return Ok(());
+            },
+            Err(reason) => output.notice(&format!("Invalid answer: {}\n", reason)),
+        }
+    }
+}
+
+/// Asks a certificate question that can be left empty to generate a self-signed certificate.
+///
+/// This function works like `ask_question`, except that an empty input asks for a generated certificate instead of
+/// selecting a default.
+///
+/// # Arguments
+///
+/// * `question` - A mutable reference to the certificate `Question`, where the answer is stored.
+/// * `base_dir` - The directory relative host paths are resolved against.
+/// * `output` - A reference to the `Output`, which prints the question on the standard error with `--stdout`.
+/// * `get_input` - A function that gets user input and returns a `Result<String, Error>`.
+///
+/// # Returns
+///
+/// * `Ok(true)` - If the input was empty and a certificate must be generated.
+/// * `Ok(false)` - If a valid answer was given and stored.
+/// * `Err(Error)` - An `Error` object that indicates an I/O error occurred while reading the input.
+///
+/// # Errors
+///
+/// This function will return an error if an I/O error occurs while reading the input.
+///
+/// # Example
+///
+/// ```
+/// if ask_certificate_question(&mut question, Path::new("."), &Output::default(), get_input).unwrap() {
+///     generate_certificate(&mut output, Path::new("."), "nginx", &CertificateOptions::default()).unwrap();
+/// }
+/// ```
+fn ask_certificate_question<F: FnMut() -> Result<String, Error>>(question: &mut Question, base_dir: &Path, output: &Output, mut get_input: F) -> Result<bool, Error> {
+    loop {
+        output.notice(&format!("{} {} (press Enter to generate a self-signed certificate and key)", question.question, describe_expected(question)));
+        let input = match get_input() {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Error reading input: {}", e);
+                return Err(e);
+            },
+        };
+        if input.trim().is_empty() {
+            return Ok(true);
+        }
+
+        match validate_answer(question, &input, base_dir) {
+            Ok(normalized) => {
+                question.answer = Some(normalized);
+                return Ok(false);
+            },
+            Err(reason) => output.notice(&format!("Invalid answer: {}\n", reason)),
+        }
     }
-    question.answer = Some(sanitized_answer);
-    
-    Ok(())
+}
+
+/// Returns the name of the project, which is the name of the output directory.
+///
+/// The output directory is canonicalized first so that `.` gives the name of the current directory. The name is then
+/// normalized like Compose does: it is lowercased, the characters other than letters, digits, `_` and `-` are removed,
+/// and so are the leading `_` and `-`, e.g. `My Stack` gives `mystack`.
+///
+/// # Arguments
+///
+/// * `output_dir` - A string that represents the output directory.
+fn project_name(output_dir: &str) -> String {
+    let path = canonicalize(output_dir).unwrap_or_else(|_| Path::new(output_dir).to_path_buf());
+    let name: String = path.file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '_' || *c == '-')
+        .collect();
+    match name.trim_start_matches(['_', '-']) {
+        "" => "ryujin".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Replaces the characters of an output directory other than alphanumerics, underscores, periods, slashes and hyphens with underscores.
+fn sanitize_output_dir(output_dir: &str) -> String {
+    output_dir.replace(|c: char| !c.is_alphanumeric() && c != '_' && c != '.' && c != '/' && c != '-', "_")
 }
 
 /// Validates the output directory and creates it if necessary.
@@ -122,29 +443,32 @@ fn ask_question<F: FnMut() -> Result<String, Error>>(question: &mut Question, mu
 /// checks if the directory exists, and if it doesn't, asks the user if they want to create it.
 ///
 /// If the directory exists, it checks if there is already a file named `docker-compose.yml` in the directory. If there
-/// is, it asks the user if they want to overwrite it.
+/// is, it asks the user if they want to overwrite it or to merge the generated services into it, unless `--merge` was given.
 ///
 /// # Arguments
 ///
 /// * `output_dir` - A string that represents the output directory.
+/// * `merge` - A `bool` that indicates whether an existing `docker-compose.yml` is merged without asking.
 /// * `get_input` - A function that returns a `Result<String, Error>`. This function is called to get the user's input.
 ///
 /// # Returns
 ///
-/// * `Ok(())` - If the output directory is valid and ready to be used.
-/// * `Err(Error)` - An `Error` object that indicates an I/O error occurred while reading the input or creating the directory.
+/// * `Ok((PathBuf, bool))` - The output directory that was chosen, which is the sanitized one given or the one entered
+///   instead, and `true` if it holds a `docker-compose.yml` to merge the generated services into.
+/// * `Err(RyujinError)` - If the input cannot be read, the directory cannot be created, or the user refuses to erase the existing file.
 ///
 /// # Errors
 ///
-/// This function will return an error if an I/O error occurs while reading the input or creating the directory.
+/// This function will return a `RyujinError::Io` if an I/O error occurs while reading the input or creating the directory,
+/// and a `RyujinError::Input` if the user refuses to erase an existing `docker-compose.yml`.
 ///
 /// # Example
 ///
 /// ```
-/// validate_output_dir("/path/to/output", get_input).unwrap();
+/// let (output_dir, merge) = validate_output_dir("/path/to/output", false, get_input).unwrap();
 /// ```
-fn validate_output_dir<F: FnMut() -> Result<String, Error>>(output_dir: &str, mut get_input: F) -> Result<(), Error> {
-    let sanitized_output_dir = output_dir.replace(|c: char| !c.is_alphanumeric() && c != '_' && c != '.' && c != '/' && c != '-', "_");
+fn validate_output_dir<F: FnMut() -> Result<String, Error>>(output_dir: &str, merge: bool, mut get_input: F) -> Result<(PathBuf, bool), RyujinError> {
+    let sanitized_output_dir = sanitize_output_dir(output_dir);
     let path = Path::new(&sanitized_output_dir);
 
     if !path.exists() {
@@ -167,12 +491,15 @@ fn validate_output_dir<F: FnMut() -> Result<String, Error>>(output_dir: &str, mu
                     return Err(e.into());
                 },
             };
-            validate_output_dir(new_dir.trim(), get_input)?;
+            return validate_output_dir(new_dir.trim(), merge, get_input);
         }
     } else {
         let docker_compose_path = path.join("docker-compose.yml");
+        if docker_compose_path.exists() && merge {
+            return Ok((path.to_path_buf(), true));
+        }
         if docker_compose_path.exists() {
-            println!("There is already a file named docker-compose.yml in {}. Do you want to erase it? (y/n)", sanitized_output_dir);
+            println!("There is already a file named docker-compose.yml in {}. Do you want to erase it, or to merge the new services into it? (y = erase, m = merge, n = keep it and exit)", sanitized_output_dir);
             let input = match get_input() {
                 Ok(input) => input,
                 Err(e) => {
@@ -180,52 +507,52 @@ fn validate_output_dir<F: FnMut() -> Result<String, Error>>(output_dir: &str, mu
                     return Err(e.into());
                 },
             };
-            if input.trim() == "y" {
+            if input.trim() == "m" {
+                return Ok((path.to_path_buf(), true));
+            } else if input.trim() == "y" {
                 remove_file(docker_compose_path)?;
             } else {
-                println!("Terminating the program.");
-                std::process::exit(1);
+                return Err(RyujinError::Input(format!("The docker-compose.yml in {} was kept, nothing was generated.", sanitized_output_dir)));
             }
         }
     }
 
-    Ok(())
+    Ok((path.to_path_buf(), false))
 }
 
 /// Generates a README file for the project.
 ///
 /// This function takes a `Services` object that represents the selected services, a `String` that represents the output directory,
-/// and a `bool` that indicates whether to overwrite existing files. It generates a README file that contains information about
-/// the selected services and writes it to the output directory. If the `overwrite` parameter is `false` and a file named `README.md`
-/// already exists in the output directory, the function will return an error.
+/// and the `Output` to write to. It generates a `readme-compose.md` file that contains information about the selected services
+/// and writes it to the output directory, replacing the one of a previous run.
 ///
 /// # Arguments
 ///
 /// * `services` - A `Services` object that contains the selected services.
 /// * `output_dir` - A `String` that represents the output directory.
-/// * `overwrite` - A `bool` that indicates whether to overwrite existing files.
+/// * `output` - A mutable reference to the `Output` the README is written to, or rendered in a dry run.
 ///
 /// # Returns
 ///
 /// * `Ok(())` - If the README file was generated successfully.
-/// * `Err(Error)` - An `Error` object that indicates an I/O error occurred while writing the file.
+/// * `Err(RyujinError)` - If the template cannot be rendered or the file cannot be written.
 ///
 /// # Errors
 ///
-/// This function will return an error if an I/O error occurs while writing the file or if `overwrite` is `false` and a file named `README.md`
-/// already exists in the output directory.
+/// This function will return a `RyujinError::Catalog` if `RYUJIN_CLI_PATH` is not set, a `RyujinError::Template` if the
+/// readme templates cannot be loaded or rendered, and a `RyujinError::Io` if an I/O error occurs while writing the file.
 ///
 /// # Example
 ///
 /// ```
 /// let services = load_services();
 /// let output_dir = "/path/to/output";
-/// readme_generator(&services, output_dir, true).unwrap();
+/// readme_generator(services, output_dir, &mut Output::default()).unwrap();
 /// ```
-fn readme_generator(selected_services: Services, output_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn readme_generator(selected_services: Services, output_dir: &str, output: &mut Output) -> Result<(), RyujinError> {
     // get the path to the ryujin-cli directory
     // it is used as the base path to the templates
-    let dir_path = env::var("RYUJIN_CLI_PATH").expect("The RYUJIN_CLI_PATH env variable was not found. Please set it to the path of the ryujin-cli directory");
+    let dir_path = ryujin_dir()?;
     // Initialize a new Tera instance and tell it to look for templates in
     // `$dir_path/services/templates/readme`.
     let tera = Tera::new(&format!("{}/services/templates/readme/*", dir_path))?;
@@ -236,9 +563,15 @@ fn readme_generator(selected_services: Services, output_dir: &str) -> Result<(),
     // Prepare the context with the sub-readme of the services.
     let mut service_templates: Vec<ReadmePartial> = Vec::new();
 
-    for (name, _) in &selected_services {
-        let informations_path = format!("{dir_path}/services/templates/readme/partials/{name}-information.md");
-        let configuration_path = format!("{dir_path}/services/templates/readme/partials/{name}-configuration.md");
+    let mut names: Vec<&String> = selected_services.keys().collect();
+    names.sort();
+
+    for name in names {
+        let service = &selected_services[name];
+        // The partials belong to the catalog service, an instance is named after itself and its service
+        let catalog_name = &service.name;
+        let informations_path = format!("{dir_path}/services/templates/readme/partials/{catalog_name}-information.md");
+        let configuration_path = format!("{dir_path}/services/templates/readme/partials/{catalog_name}-configuration.md");
 
         let informations_readme = read_to_string(&informations_path)
             .unwrap_or_else(|_| format!("Could not read {}", informations_path));
@@ -246,9 +579,10 @@ fn readme_generator(selected_services: Services, output_dir: &str) -> Result<(),
             .unwrap_or_else(|_| format!("Could not read {}", configuration_path));
 
         service_templates.push(ReadmePartial {
-            service: name.clone(),
+            service: if is_instance(name, service) { format!("{} ({})", name, catalog_name) } else { name.clone() },
             informations_readme,
             configuration_readme,
+            profiles: service.profiles.clone(),
         });
     }
     
@@ -258,124 +592,583 @@ fn readme_generator(selected_services: Services, output_dir: &str) -> Result<(),
     match tera.render("readme-template-readme.md", &context) {
         Ok(rendered) => {
             // Write the rendered content to the file
-            let mut file = File::create(format!("{}/readme-compose.md", output_dir))?;
-            file.write_all(rendered.as_bytes())?;
+            output.write(&Path::new(output_dir).join("readme-compose.md"), &rendered, None)?;
         },
         Err(e) => {
-            eprintln!("Error rendering template: {:?}", e);
-            return Err(Box::new(e));
+            return Err(e.into());
         },
     }
 
     Ok(())
 }
 
+/// Escapes a value rendered by a service template for a double-quoted YAML scalar.
+///
+/// The templates quote every `{{ ... }}`, so an answer such as `/srv/my #site` or `a: b` stays one value instead of
+/// being cut short, or of breaking the parsing of the rendered template.
+///
+/// # Arguments
+///
+/// * `value` - The rendered value.
+fn escape_yaml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            character if character.is_control() => escaped.push_str(&format!("\\u{:04x}", character as u32)),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// Creates the Tera instance the service templates are rendered with: the values rendered in the `.yml` templates are
+/// escaped by `escape_yaml`.
+fn service_templates() -> Tera {
+    let mut tera = Tera::default();
+    tera.autoescape_on(vec![".yml"]);
+    tera.set_escape_fn(escape_yaml);
+    tera
+}
+
+/// Builds the Tera context used to render the template of a service.
+///
+/// The context holds the answer of each question under the key of the question's variable, and the list of the
+/// variables of the skipped questions under `skipped`, and the host port of each port of the service under `ports`. The answers of secret questions are not added as is: the
+/// context refers to them with `${VAR}` interpolation and they are pushed to `env_entries` instead.
+///
+/// The variables of an instance are namespaced by its name in the `.env` file, e.g. `SITE_A_CERTIFICATE_PATH`.
+///
+/// With `--env-file`, every answer and every host port is handled like a secret, a port fixed in the catalog getting a
+/// `<SERVICE>_<PORT>_PORT` variable. Only the `bool` answers, tested by the templates, and the named volumes, declared
+/// by name in the top-level `volumes` section, stay as is.
+///
+/// # Arguments
+///
+/// * `name` - The name of the service in the selection, which is the name of the instance for an instance.
+/// * `service` - A reference to the `Service` whose template is rendered.
+/// * `env_file` - A `bool` that indicates whether every answer goes to the `.env` file.
+/// * `env_entries` - A mutable reference to the entries of the `.env` file.
+fn service_context(name: &str, service: &Service, env_file: bool, env_entries: &mut Vec<EnvEntry>) -> Context {
+    let mut context = Context::new();
+    let mut skipped: Vec<String> = Vec::new();
+
+    for question in &service.questions {
+        if let Some(answer) = &question.answer {
+            if question.secret || (env_file && is_parameterized(question, answer)) {
+                let variable = namespaced(name, service, &question.variable);
+                context.insert(&question.variable, &interpolation(&variable));
+                env_entries.push(EnvEntry {
+                    name: env_variable_name(&variable),
+                    value: answer.clone(),
+                    comment: question.question.clone(),
+                });
+            } else {
+                insert_answer(&mut context, question, answer);
+            }
+        }
+        if question.skipped {
+            skipped.push(question.variable.clone());
+        }
+    }
+    context.insert("skipped", &skipped);
+
+    let mut ports: HashMap<String, String> = host_ports(service).into_iter()
+        .map(|(name, host)| (name, host.to_string()))
+        .collect();
+    if env_file {
+        for mapping in &service.ports {
+            let Some(host) = ports.get_mut(&mapping.name) else { continue };
+            match &mapping.variable {
+                Some(variable) => *host = interpolation(&namespaced(name, service, variable)),
+                // A port fixed in the catalog gets a variable of its own
+                None => {
+                    let variable = format!("{}_{}_port", name, mapping.name);
+                    env_entries.push(EnvEntry {
+                        name: env_variable_name(&variable),
+                        value: host.clone(),
+                        comment: format!("The host port of the {} port of {} (container port {}).", mapping.name, name, mapping.container),
+                    });
+                    *host = interpolation(&variable);
+                },
+            }
+        }
+    }
+    context.insert("ports", &ports);
+
+    context
+}
+
+/// Tells whether the answer of a question can be replaced by a `${VAR}` interpolation with `--env-file`.
+fn is_parameterized(question: &Question, answer: &str) -> bool {
+    match question.kind {
+        QuestionKind::Bool => false,
+        QuestionKind::Volume => !is_named_volume(answer),
+        _ => true,
+    }
+}
+
+/// Checks a docker-compose against the Compose specification before it is written.
+///
+/// # Arguments
+///
+/// * `content` - The content of the docker-compose.
+/// * `origin` - How the content was made, `generated` or `merged`, used in the error message.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Template` listing the problems found by `validate_compose`.
+fn check_compose(content: &str, origin: &str) -> Result<(), RyujinError> {
+    let diagnostics = validate_compose(content, "docker-compose.yml");
+    if diagnostics.is_empty() {
+        return Ok(());
+    }
+    let report: Vec<String> = diagnostics.iter().map(|diagnostic| format!("{}: {}", diagnostic.location, diagnostic.message)).collect();
+    Err(RyujinError::Template(format!("The {} docker-compose does not follow the Compose specification:\n{}", origin, report.join("\n"))))
+}
+
 /// Generates a `docker-compose.yml` file based on the selected services and their answers to questions.
 ///
-/// This function uses the Tera templating engine to render a `docker-compose.yml` file. It first creates a context
-/// and adds the names of the selected services to it. Then, it loops over the selected services and their questions,
-/// and adds the answer of each question to the context under the key of the question's variable. The context is then
-/// used to render the `template-docker-compose.yml` template, which includes service-specific templates based on the
-/// names of the selected services. The rendered template is written to a `docker-compose.yml` file in the specified
-/// output directory.
+/// This function uses the Tera templating engine to render a `docker-compose.yml` file. Each selected service is
+/// rendered from its own `template_path` with a context that holds the answers to its questions (see `service_context`),
+/// and attached to the `networks` listed in the catalog. The `volume` questions answered with a volume name are declared
+/// in the top-level `volumes` section, with the driver and the driver options of the question. With `--docker-secrets`,
+/// the `secret_files` of the services are mounted as compose secrets instead of bind mounts. The `healthcheck` of a
+/// service, once tuned by the command line options, is written to each compose service of its template. A service
+/// `depends_on` the selected services it `requires` or starts `optional_after`, with the `service_healthy` condition
+/// when they have a healthcheck, unless the profiles of the dependency may leave it stopped. The services selected with
+/// profiles (`<service>@<profile>`) get the `profiles` key. The resource limits computed from `--size` and `--limit` are written to each compose
+/// service of the template, and so are the hardening options of the security profile (see the `security` module).
+/// The group of a generated TLS key is added to the compose services of its service (`group_add`), so they can read it.
+/// Each rendered template is parsed into the typed compose model (see the `compose_model` module), which rejects unknown
+/// keys and malformed YAML, and the `docker-compose.yml` file written to the output directory is serialized from the model
+/// and checked against the Compose specification (see the `validate` module). The answers of secret questions are not written in the compose file: they
+/// are written to a `.env` file with `0600` permissions and the compose file refers to them with `${VAR}` interpolation.
+/// With `--env-file`, the other answers are interpolated from the `.env` file too, so they can be changed without
+/// generating the compose file again.
 ///
 /// # Arguments
 ///
 /// * `selected_services` - A `Services` instance representing the services selected by the user. Each service has a
 ///   list of questions, and each question has an answer that is used to populate the service's template.
 /// * `output_dir` - A string slice representing the directory where the `docker-compose.yml` file will be written.
+/// * `options` - A reference to the `ComposeOptions` of the command, for the networks marked as internal and the Docker secrets.
+/// * `limits` - A reference to the `ResourceLimits` of the selected services, keyed by service name (see `resolve_limits`).
+/// * `merge` - A `bool` that indicates whether the services are merged into the existing `docker-compose.yml` (see
+///   `merge_compose`) instead of replacing it. The conflicting keys are shown, and confirmed by the user when possible,
+///   before the file is written. The existing variables of the `.env` file are kept too.
+/// * `output` - A mutable reference to the `Output` the files are written to, or rendered in a dry run.
 ///
 /// # Returns
 ///
 /// This function returns a `Result`. If the function succeeds, it returns `Ok(())`. If the function fails, it returns
 /// `Err` with the error that occurred. Potential errors include Tera being unable to find, parse, or render a template,
-/// or the program being unable to write to the `docker-compose.yml` file.
+/// a rendered template that is not a valid compose service, or the program being unable to write to the `docker-compose.yml` file.
 ///
 /// # Example
 ///
 /// ```rust
 /// let selected_services = get_selected_services();
 /// let output_dir = "./output";
-/// docker_compose_generator(selected_services, output_dir).unwrap();
+/// docker_compose_generator(selected_services, output_dir, &ComposeOptions::default(), &HashMap::new(), false, &mut Output::default()).unwrap();
 /// ```
-fn docker_compose_generator(selected_services: Services, output_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn docker_compose_generator(selected_services: Services, output_dir: &str, options: &ComposeOptions, limits: &HashMap<String, ResourceLimits>, merge: bool, output: &mut Output) -> Result<(), RyujinError> {
     // get the path to the ryujin-cli directory
     // it is used as the base path to the templates
-    let dir_path = env::var("RYUJIN_CLI_PATH").expect("The RYUJIN_CLI_PATH env variable was not found. Please set it to the path of the ryujin-cli directory");
-    
-    // Initialize a new Tera instance and tell it to look for templates in the `$dir_path/services/templates` directory.
-    let tera = Tera::new(&format!("{}/services/templates/compose/*", dir_path))?;
+    let dir_path = ryujin_dir()?;
 
-    // Create an empty `Context` instance to hold the variables for the template.
-    let mut context = Context::new();
+    let mut tera = service_templates();
+    let mut compose = ComposeFile::new();
 
-    // Add the selected services to the context.
-    let service_names: Vec<String> = selected_services.keys().cloned().collect();
-    context.insert("services", &service_names);
+    // Render each selected service from its own template, in alphabetical order, and add it to the compose model.
+    let mut service_names: Vec<&String> = selected_services.keys().collect();
+    service_names.sort();
 
-    // Add the answers to the questions to the context.
-    for service in selected_services.values() {
-        for question in &service.questions {
-            if let Some(answer) = &question.answer {
-                context.insert(&question.variable, answer);
+    let mut env_entries: Vec<EnvEntry> = Vec::new();
+    let mut compose_names: HashMap<&String, Vec<String>> = HashMap::new();
+    for name in &service_names {
+        let name = *name;
+        let service = &selected_services[name];
+        let template_name = format!("{}.yml", name);
+        tera.add_template_file(format!("{}/{}", dir_path, service.template_path), Some(&template_name))?;
+        let context = service_context(name, service, options.env_file, &mut env_entries);
+        let rendered = tera.render(&template_name, &context)?;
+        let instance = Some(name.as_str()).filter(|name| is_instance(name, service));
+        let compose_services = compose.add_services(&rendered, &service.template_path, instance)?;
+        compose_names.insert(name, compose_services.clone());
+        compose.attach_networks(&compose_services, &service.networks, &options.internal_networks);
+
+        if let Some(healthcheck) = &service.healthcheck {
+            let healthcheck = ComposeHealthcheck {
+                test: healthcheck.test.clone(),
+                interval: healthcheck.interval.clone(),
+                timeout: healthcheck.timeout.clone(),
+                retries: healthcheck.retries,
+                start_period: healthcheck.start_period.clone(),
+                disable: None,
+            };
+            compose.set_healthcheck(&compose_services, &healthcheck);
+        }
+
+        if let Some(limits) = limits.get(name) {
+            let limits = DeployLimits { cpus: limits.cpus.clone(), memory: limits.memory.clone(), pids: limits.pids };
+            compose.limit_resources(&compose_services, &limits);
+        }
+
+        compose.harden(&compose_services, &hardening_for(service, options.security));
+        if let Some(group) = service.key_group {
+            compose.add_group(&compose_services, group);
+        }
+        compose.add_profiles(&compose_services, &service.profiles);
+
+        // The volume questions answered with a volume name need a top-level declaration
+        for question in service.questions.iter().filter(|question| question.kind == QuestionKind::Volume) {
+            if let Some(answer) = question.answer.as_ref().filter(|answer| is_named_volume(answer)) {
+                let volume = ComposeVolume {
+                    driver: question.driver.clone(),
+                    driver_opts: question.driver_opts.iter().map(|(key, value)| (key.clone(), value.clone())).collect(),
+                };
+                compose.declare_volume(answer, volume)?;
+            }
+        }
+
+        // The secret files answered are mounted as Docker secrets instead of bind mounts
+        if options.docker_secrets {
+            for secret_file in &service.secret_files {
+                let answer = service.questions.iter()
+                    .find(|question| question.variable == secret_file.variable)
+                    .and_then(|question| question.answer.as_ref());
+                if let Some(file) = answer {
+                    let secret = namespaced(name, service, &secret_file.variable);
+                    let file = if options.env_file { interpolation(&secret) } else { file.clone() };
+                    compose.mount_secret(&compose_services, &secret, &file, &secret_file.target);
+                }
             }
         }
     }
 
-    // Render the `template-docker-compose.yml` template with the context.
-    let docker_compose_content = tera.render("template-docker-compose.yml", &context)?;
-    
-// Write the `docker_compose.yml` content to a file in the `output_dir`.
-    let mut file = File::create(format!("{}/docker-compose.yml", output_dir))?;
-    file.write_all(docker_compose_content.as_bytes())?;
+    // The dependencies are known by their catalog name, depends_on needs the compose service names of their templates
+    for name in &service_names {
+        for dependency in dependencies_of(&selected_services[*name], &selected_services) {
+            // A required service is checked at selection, a service started after may be left out by the profiles
+            if !can_depend_on(&selected_services[*name], &selected_services[&dependency]) {
+                output.notice(&format!("Warning: {} does not wait for {}, which only starts with the profiles {}.", name, dependency, selected_services[&dependency].profiles.join(", ")));
+                continue;
+            }
+            let healthy = selected_services[&dependency].healthcheck.is_some();
+            compose.add_dependencies(&compose_names[*name], &compose_names[&dependency], healthy);
+        }
+    }
+
+    // A container only attached to internal networks cannot be reached on its published ports.
+    for (name, service) in &compose.services {
+        let isolated = !service.networks.is_empty() && service.networks.iter().all(|network| options.internal_networks.contains(network));
+        if isolated && !service.ports.is_empty() {
+            output.notice(&format!("Warning: {} publishes ports but is only attached to internal networks, its ports will not be reachable.", name));
+        }
+    }
+
+    let docker_compose_content = compose.to_yaml()?;
+
+    // Check the result against the Compose specification before writing anything.
+    check_compose(&docker_compose_content, "generated")?;
+
+    // Two services can share a variable, as long as they agree on its value.
+    let env_entries = unique_entries(env_entries)?;
+
+    // Merge the services into the existing file, once the user saw what changes
+    let docker_compose_path = Path::new(output_dir).join("docker-compose.yml");
+    let docker_compose_content = if merge {
+        let existing = read_to_string(&docker_compose_path)?;
+        let (merged, report) = merge_compose(&existing, &compose)?;
+        check_compose(&merged, "merged")?;
+        confirm_merge(&report, output, std::io::stdin().is_terminal(), get_input)?;
+        merged
+    } else {
+        docker_compose_content
+    };
+
+    // Write the `docker_compose.yml` content to a file in the `output_dir`.
+    output.write(&docker_compose_path, &docker_compose_content, None)?;
+
+    // Write the secrets to the `.env` file next to the `docker-compose.yml`.
+    if !env_entries.is_empty() {
+        write_env_file(output, output_dir, &env_entries, merge)?;
+    }
 
     Ok(())
 }
 
+/// `ComposeOptions` is a struct that holds the options of the compose command.
+///
+/// It contains the following fields:
+/// * `answers_path`: An `Option<String>` that represents the path to a JSON, YAML or TOML answers file.
+/// * `port_remaps`: A `Vec<PortRemap>` that contains the host ports given with `--port`.
+/// * `internal_networks`: A `Vec<String>` that contains the networks given with `--internal-networks`.
+/// * `docker_secrets`: A `bool` that indicates whether the secret files of the services are mounted as Docker secrets.
+/// * `healthcheck_tunings`: A `Vec<HealthcheckTuning>` that contains the healthcheck settings given with `--healthcheck`.
+/// * `no_healthcheck`: An `Option<Vec<String>>` that contains the services given with `--no-healthcheck`. An empty list disables every healthcheck.
+/// * `size`: A `Size` that represents the preset of the resource limits given with `--size`.
+/// * `limit_overrides`: A `Vec<LimitOverride>` that contains the resource limits given with `--limit`.
+/// * `security`: A `SecurityLevel` that represents the security profile given with `--security`.
+/// * `merge`: A `bool` that indicates whether an existing `docker-compose.yml` is merged without asking.
+/// * `env_file`: A `bool` that indicates whether every answer is written to the `.env` file and interpolated in the compose file.
+/// * `certificates`: A `CertificateOptions` that holds the `--self-signed`, `--tls-cn`, `--tls-san` and `--tls-ca` options.
+/// * `dry_run`: A `bool` that indicates whether the files are rendered and listed without being written.
+/// * `stdout`: A `bool` that indicates whether the `docker-compose.yml` is printed on the standard output instead of being written.
+#[derive(Debug, Default, Clone)]
+pub struct ComposeOptions {
+    pub answers_path: Option<String>,
+    pub port_remaps: Vec<PortRemap>,
+    pub internal_networks: Vec<String>,
+    pub docker_secrets: bool,
+    pub healthcheck_tunings: Vec<HealthcheckTuning>,
+    pub no_healthcheck: Option<Vec<String>>,
+    pub size: Size,
+    pub limit_overrides: Vec<LimitOverride>,
+    pub security: SecurityLevel,
+    pub merge: bool,
+    pub env_file: bool,
+    pub certificates: CertificateOptions,
+    pub dry_run: bool,
+    pub stdout: bool,
+}
+
 /// Composes the Docker services based on user's choices.
 ///
 /// This function takes a reference to the available services, a vector of user's chosen services, and an output directory.
 /// It validates the output directory, selects the chosen services, asks the user questions related to each chosen service,
 /// and generates the Docker compose file in the output directory.
 ///
+/// When an answers file is given, its answers are used first, the missing questions fall back to their default,
+/// and only the questions without default are asked.
+///
+/// Once the questions are answered, the `--port` remaps are applied and the host ports published by several services
+/// are reported. They are remapped interactively when the standard input is a terminal.
+///
+/// With `--dry-run` or `--stdout`, the output directory is neither checked nor created: the files are rendered in an
+/// `Output` and listed at the end, and the `docker-compose.yml` is printed with `--stdout`.
+///
 /// # Arguments
 ///
 /// * `available_services` - A reference to a `Services` object that contains all available services.
 /// * `chosen_services` - A vector of `String` that represents the user's chosen services.
 /// * `output_dir` - A string that represents the output directory.
+/// * `options` - A reference to the `ComposeOptions` of the command.
 ///
 /// # Returns
 ///
 /// * `Ok(())` - If the Docker compose file was successfully generated.
-/// * `Err(Error)` - An `Error` object that indicates an error occurred while validating the output directory, selecting the services, asking the questions, or generating the Docker compose file.
+/// * `Err(RyujinError)` - If an error occurred while validating the output directory, selecting the services, asking the questions, or generating the Docker compose file.
 ///
 /// # Errors
 ///
-/// This function will return an error if an error occurs while validating the output directory, selecting the services, asking the questions, or generating the Docker compose file.
+/// This function will return a `RyujinError` whose variant tells which step failed; see `RyujinError` for the exit code of each variant.
 ///
 /// # Example
 ///
 /// ```
 /// let services = load_services();
 /// let user_choice = vec!["apache".to_string(), "mongodb".to_string()];
-/// compose(&services, user_choice, "./output").unwrap();
+/// compose(&services, user_choice, "./output", &ComposeOptions::default()).unwrap();
 /// ```
-pub fn handle(available_services: &Services, choosen_services: Vec<String>, output_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Validate the output directory
-    validate_output_dir(output_dir, get_input)?;
+pub fn handle(available_services: &Services, choosen_services: Vec<String>, output_dir: &str, options: &ComposeOptions) -> Result<(), RyujinError> {
+    // Load the answers file before any prompt so a bad file fails fast
+    let answers = match &options.answers_path {
+        Some(path) => Some(load_answers(path)?),
+        None => None,
+    };
+
+    // Validate the output directory, a dry run does not touch it
+    let mut output = Output::new(options.dry_run, options.stdout);
+    let (output_dir, merge) = if output.dry_run {
+        let output_dir = PathBuf::from(sanitize_output_dir(output_dir));
+        let merge = options.merge && output_dir.join("docker-compose.yml").is_file();
+        (output_dir, merge)
+    } else {
+        validate_output_dir(output_dir, options.merge, get_input)?
+    };
+    // The directory entered instead of a missing one is used from here on
+    let output_dir = output_dir.to_string_lossy().into_owned();
+    let output_dir = output_dir.as_str();
 
     // Get the selected services
-    let mut selected_services = get_selected_services(available_services, choosen_services)?;
+    let mut selected_services = get_selected_services(available_services, choosen_services, std::io::stdin().is_terminal(), &output, get_input)?;
+
+    // The internal networks must be used by the selection, a typo would leave the backend exposed
+    for network in &options.internal_networks {
+        if !selected_services.values().any(|service| service.networks.contains(network)) {
+            return Err(RyujinError::Input(format!("The network {} is not used by the selected services and cannot be made internal.", network)));
+        }
+    }
+
+    // Tune or disable the healthchecks before any prompt, they do not depend on the answers
+    configure_healthchecks(&mut selected_services, options.no_healthcheck.as_deref(), &options.healthcheck_tunings)?;
+    let limits = resolve_limits(&selected_services, options.size, &options.limit_overrides)?;
+
+    // Fill the questions with the answers file
+    if let Some(answers) = &answers {
+        apply_answers(&mut selected_services, answers)?;
+    }
 
     // Ask questions for the selected services
-    ask_services_questions(&mut selected_services)?;
+    let project_name = project_name(output_dir);
+    ask_services_questions(&mut selected_services, Path::new(output_dir), &project_name, answers.is_some(), &options.certificates, &mut output)?;
+
+    // Remap the host ports published by several services
+    apply_remaps(&mut selected_services, &options.port_remaps)?;
+    resolve_conflicts(&mut selected_services, std::io::stdin().is_terminal(), &output, get_input)?;
 
     // Generate the docker compose file
-    docker_compose_generator(selected_services.clone(), output_dir)?;
+    docker_compose_generator(selected_services.clone(), output_dir, options, &limits, merge, &mut output)?;
 
     // Generate the readme for the docker compose 
-    readme_generator(selected_services, output_dir)?;
-    
+    readme_generator(selected_services, output_dir, &mut output)?;
+
+    // Show what a dry run rendered
+    output.print();
+
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_name_is_normalized_like_compose() {
+        assert_eq!(project_name("/nonexistent/my stack"), "mystack");
+        assert_eq!(project_name("/nonexistent/My_Project-2"), "my_project-2");
+        assert_eq!(project_name("/nonexistent/-.web.site"), "website");
+        assert_eq!(project_name("/nonexistent/été"), "t");
+        assert_eq!(project_name("/nonexistent/!!!"), "ryujin");
+    }
+
+    fn catalog_question(service: &str, variable: &str) -> Question {
+        let path = format!("{}/services/services.json", env!("CARGO_MANIFEST_DIR"));
+        let catalog: Services = serde_json::from_str(&read_to_string(path).unwrap()).unwrap();
+        catalog[service].questions.iter().find(|question| question.variable == variable).unwrap().clone()
+    }
+
+    #[test]
+    fn a_question_is_asked_when_its_condition_holds() {
+        let npm = catalog_question("react-nginx", "package_lock_json");
+        let yarn = catalog_question("react-nginx", "yarn_lock");
+        let mut context = Context::new();
+        context.insert("package_manager", "npm");
+
+        assert!(condition_holds(&npm, &context).unwrap());
+        assert!(!condition_holds(&yarn, &context).unwrap());
+        assert!(condition_holds(&catalog_question("react-nginx", "src_path"), &context).unwrap());
+
+        // A bool answer is tested as a boolean
+        let mut question = npm.clone();
+        question.when = Some("tls and package_manager != \"yarn\"".to_string());
+        let mut context = Context::new();
+        insert_answer(&mut context, &Question { kind: QuestionKind::Bool, variable: "tls".to_string(), ..npm.clone() }, "false");
+        context.insert("package_manager", "npm");
+        assert!(!condition_holds(&question, &context).unwrap());
+
+        question.when = Some("package_manager ==".to_string());
+        assert!(matches!(condition_holds(&question, &context), Err(RyujinError::Catalog(_))));
+    }
+
+    #[test]
+    fn defaults_are_computed_from_the_answers() {
+        let mut context = Context::new();
+        context.insert("project_name", "mystack");
+        context.insert("service", "site-a");
+
+        assert_eq!(render_default(&catalog_question("portainer", "portainer_container_name"), &context).unwrap().as_deref(), Some("mystack-site-a"));
+        assert_eq!(render_default(&catalog_question("portainer", "portainer_volume"), &context).unwrap().as_deref(), Some("site-a_data"));
+        assert_eq!(render_default(&catalog_question("portainer", "ui_port"), &context).unwrap().as_deref(), Some("9443"));
+        assert_eq!(render_default(&catalog_question("react-nginx", "src_path"), &context).unwrap(), None);
+
+        let mut question = catalog_question("portainer", "ui_port");
+        question.default = Some("{{ unknown_answer }}".to_string());
+        assert!(matches!(render_default(&question, &context), Err(RyujinError::Catalog(_))));
+    }
+
+    #[test]
+    fn answers_are_escaped_for_double_quoted_yaml() {
+        assert_eq!(escape_yaml("/srv/my site"), "/srv/my site");
+        assert_eq!(escape_yaml(r#"C:\web "site""#), r#"C:\\web \"site\""#);
+        assert_eq!(escape_yaml("a\tb"), "a\\u0009b");
+    }
+
+    #[test]
+    fn special_characters_of_an_answer_reach_the_compose_model() {
+        let mut tera = service_templates();
+        tera.add_template_file(format!("{}/services/templates/compose/template-nginx.yml", env!("CARGO_MANIFEST_DIR")), Some("nginx.yml")).unwrap();
+        let mut context = Context::new();
+        context.insert("certificate_path", "/srv/#certs/site.crt");
+        context.insert("private_key_path", "{ key }");
+        context.insert("nginx_content", "*site: \"a\" \\ b");
+        context.insert("ports", &HashMap::from([("http", 8080), ("https", 8443)]));
+        let rendered = tera.render("nginx.yml", &context).unwrap();
+
+        let mut compose = ComposeFile::new();
+        compose.add_services(&rendered, "template-nginx.yml", None).unwrap();
+        assert_eq!(compose.services["nginx"].volumes, vec![
+            "/srv/#certs/site.crt:/etc/nginx/ssl/certs/nginx.crt",
+            "{ key }:/etc/nginx/ssl/private/nginx.key",
+            "*site: \"a\" \\ b:/usr/share/nginx/html/",
+        ]);
+        assert_eq!(compose.services["nginx"].ports, vec!["8080:80", "8443:443"]);
+    }
+
+    /// Answers the prompts of `validate_output_dir` with `inputs`, in order.
+    fn scripted(inputs: &[&str]) -> impl FnMut() -> Result<String, Error> {
+        let mut inputs: Vec<String> = inputs.iter().rev().map(|input| input.to_string()).collect();
+        move || inputs.pop().ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "no more input"))
+    }
+
+    #[test]
+    fn the_directory_entered_instead_of_a_missing_one_is_returned() {
+        let dir = std::env::temp_dir().join(format!("ryujin-output-dir-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let missing = dir.join("missing");
+        let entered = dir.join("entered");
+        create_dir_all(&entered).unwrap();
+
+        let result = validate_output_dir(missing.to_str().unwrap(), false, scripted(&["n", entered.to_str().unwrap()]));
+        let missing_created = missing.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(result.unwrap(), (entered, false));
+        assert!(!missing_created);
+    }
+
+    #[test]
+    fn the_sanitized_directory_is_created_and_returned() {
+        let dir = std::env::temp_dir().join(format!("ryujin-output-dir-sanitized-{}", std::process::id()));
+        let given = dir.join("my stack");
+
+        let result = validate_output_dir(given.to_str().unwrap(), false, scripted(&["y"]));
+        let created = dir.join("my_stack").is_dir();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(result.unwrap(), (dir.join("my_stack"), false));
+        assert!(created);
+    }
+
+    #[test]
+    fn an_existing_docker_compose_is_merged_or_kept() {
+        let dir = std::env::temp_dir().join(format!("ryujin-output-dir-merge-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("docker-compose.yml"), "services: {}\n").unwrap();
+        let given = dir.to_str().unwrap();
+
+        let merged = validate_output_dir(given, false, scripted(&["m"]));
+        let forced = validate_output_dir(given, true, scripted(&[]));
+        let kept = validate_output_dir(given, false, scripted(&["n"]));
+        let kept_on_disk = dir.join("docker-compose.yml").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(merged.unwrap(), (dir.clone(), true));
+        assert_eq!(forced.unwrap(), (dir, true));
+        assert!(matches!(kept, Err(RyujinError::Input(_))));
+        assert!(kept_on_disk);
+    }
+}
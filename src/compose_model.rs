@@ -0,0 +1,540 @@
+use crate::error::RyujinError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+
+/// `ComposeFile` is a struct that represents a `docker-compose.yml` file.
+///
+/// It contains the following fields:
+/// * `version`: An `Option<String>` that represents the obsolete version of the compose file format, if the file declares one.
+/// * `services`: A `BTreeMap<String, ComposeService>` that contains the services, keyed by compose service name.
+/// * `volumes`: A `BTreeMap<String, ComposeVolume>` that contains the named volumes.
+/// * `networks`: A `BTreeMap<String, ComposeNetwork>` that contains the networks.
+/// * `secrets`: A `BTreeMap<String, ComposeSecret>` that contains the secrets.
+///
+/// The maps are sorted by key so that the generated file does not depend on the order of the selection.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub services: BTreeMap<String, ComposeService>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub volumes: BTreeMap<String, ComposeVolume>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub networks: BTreeMap<String, ComposeNetwork>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: BTreeMap<String, ComposeSecret>,
+}
+
+/// `ComposeService` is a struct that represents a service of a `docker-compose.yml` file.
+///
+/// It contains the following fields:
+/// * `image`: An `Option<String>` that represents the image the container is created from.
+/// * `build`: An `Option<Build>` that represents how the image is built.
+/// * `container_name`: An `Option<String>` that represents the name of the container.
+/// * `profiles`: A `Vec<String>` that contains the profiles the service belongs to. A service without profiles always starts.
+/// * `restart`: An `Option<String>` that represents the restart policy.
+/// * `tty`: An `Option<bool>` that indicates whether a TTY is allocated.
+/// * `ports`: A `Vec<String>` that contains the published ports, as `host:container`.
+/// * `volumes`: A `Vec<String>` that contains the mounted volumes, as `source:target`.
+/// * `environment`: A `BTreeMap<String, String>` that contains the environment variables of the container.
+/// * `networks`: A `Vec<String>` that contains the networks the container is attached to.
+/// * `secrets`: A `Vec<ServiceSecret>` that contains the secrets mounted in the container.
+/// * `healthcheck`: An `Option<ComposeHealthcheck>` that represents how Docker checks that the container is healthy.
+/// * `depends_on`: A `BTreeMap<String, ComposeDependency>` that contains the services started before this one, keyed by compose service name.
+/// * `deploy`: An `Option<Deploy>` that represents the deployment settings, for the resource limits.
+/// * `mem_limit`: An `Option<String>` that represents the memory limit, read by the engines that ignore `deploy`.
+/// * `pids_limit`: An `Option<u32>` that represents the process limit, read by the engines that ignore `deploy`.
+/// * `user`: An `Option<String>` that represents the user the processes of the container run as.
+/// * `group_add`: A `Vec<String>` that contains the additional groups of the processes, as group IDs of the host.
+/// * `read_only`: An `Option<bool>` that indicates whether the root filesystem of the container is read-only.
+/// * `tmpfs`: A `Vec<String>` that contains the directories mounted as tmpfs.
+/// * `cap_drop`: A `Vec<String>` that contains the capabilities removed from the container, e.g. `ALL`.
+/// * `cap_add`: A `Vec<String>` that contains the capabilities added to the container.
+/// * `security_opt`: A `Vec<String>` that contains the security options, e.g. `no-new-privileges:true`.
+///
+/// Unknown keys are rejected, so a typo in a template (e.g. `images:`) fails the generation instead of `docker compose up`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeService {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<Build>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tty: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub environment: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub networks: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secrets: Vec<ServiceSecret>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<ComposeHealthcheck>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub depends_on: BTreeMap<String, ComposeDependency>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deploy: Option<Deploy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_limit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pids_limit: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub group_add: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tmpfs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cap_drop: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cap_add: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub security_opt: Vec<String>,
+}
+
+/// `Hardening` is a struct that represents the hardening options added to a service by a security profile.
+///
+/// It contains the same fields as the hardening keys of `ComposeService`. It is not serialized.
+#[derive(Debug, Clone, Default)]
+pub struct Hardening {
+    pub user: Option<String>,
+    pub read_only: bool,
+    pub tmpfs: Vec<String>,
+    pub cap_drop: Vec<String>,
+    pub cap_add: Vec<String>,
+    pub security_opt: Vec<String>,
+}
+
+/// `Deploy` is a struct that represents the `deploy` key of a service.
+///
+/// It contains the following fields:
+/// * `resources`: A `DeployResources` that represents the resources of the container.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Deploy {
+    #[serde(default)]
+    pub resources: DeployResources,
+}
+
+/// `DeployResources` is a struct that represents the `deploy.resources` key of a service.
+///
+/// It contains the following fields:
+/// * `limits`: A `DeployLimits` that represents the most the container can use.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DeployResources {
+    #[serde(default)]
+    pub limits: DeployLimits,
+}
+
+/// `DeployLimits` is a struct that represents the `deploy.resources.limits` key of a service.
+///
+/// It contains the following fields:
+/// * `cpus`: An `Option<String>` that represents the number of CPUs, e.g. `0.5`.
+/// * `memory`: An `Option<String>` that represents the amount of memory, e.g. `512m`.
+/// * `pids`: An `Option<u32>` that represents the number of processes.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DeployLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pids: Option<u32>,
+}
+
+/// `ComposeDependency` is a struct that represents a service of the `depends_on` key of a service, in the long syntax.
+///
+/// It contains the following fields:
+/// * `condition`: A `String` that represents when the dependency is ready: `service_started` or `service_healthy`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeDependency {
+    pub condition: String,
+}
+
+/// `ComposeHealthcheck` is a struct that represents the `healthcheck` key of a service.
+///
+/// It contains the following fields:
+/// * `test`: A `Vec<String>` that represents the command run in the container, starting with `CMD`, `CMD-SHELL` or `NONE`.
+/// * `interval`: An `Option<String>` that represents the duration between two checks.
+/// * `timeout`: An `Option<String>` that represents the duration after which a check is considered failed.
+/// * `retries`: An `Option<u32>` that represents the number of consecutive failures needed to report the container as unhealthy.
+/// * `start_period`: An `Option<String>` that represents the time given to the container to start before the failures are counted.
+/// * `disable`: An `Option<bool>` that disables the healthcheck defined by the image.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeHealthcheck {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub test: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_period: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disable: Option<bool>,
+}
+
+/// `ServiceSecret` is an enum that represents a secret mounted in a service.
+///
+/// * `Name`: The short syntax, the secret is mounted in `/run/secrets/<name>`.
+/// * `Config`: The long syntax, with the `source` secret and the `target` path in the container.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ServiceSecret {
+    Name(String),
+    Config {
+        source: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+    },
+}
+
+/// `Build` is an enum that represents the `build` key of a service.
+///
+/// * `Context`: The short syntax, only the build context.
+/// * `Config`: The long syntax, see `BuildConfig`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Build {
+    Context(String),
+    Config(BuildConfig),
+}
+
+/// `BuildConfig` is a struct that represents the long syntax of the `build` key of a service.
+///
+/// It contains the following fields:
+/// * `context`: A `String` that represents the build context, a directory or a git URL.
+/// * `dockerfile`: An `Option<String>` that represents the Dockerfile, relative to the context.
+/// * `target`: An `Option<String>` that represents the stage of a multi-stage Dockerfile.
+/// * `args`: A `BTreeMap<String, String>` that contains the build arguments.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BuildConfig {
+    pub context: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dockerfile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub args: BTreeMap<String, String>,
+}
+
+/// `ComposeVolume` is a struct that represents a named volume of a `docker-compose.yml` file.
+///
+/// It contains the following fields:
+/// * `driver`: An `Option<String>` that represents the volume driver. Docker uses `local` when it is not set.
+/// * `driver_opts`: A `BTreeMap<String, String>` that contains the options of the volume driver.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeVolume {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub driver_opts: BTreeMap<String, String>,
+}
+
+/// `ComposeNetwork` is a struct that represents a network of a `docker-compose.yml` file.
+///
+/// It contains the following fields:
+/// * `driver`: An `Option<String>` that represents the network driver. Docker uses `bridge` when it is not set.
+/// * `internal`: An `Option<bool>` that indicates whether the network is cut from the outside world.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeNetwork {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub internal: Option<bool>,
+}
+
+/// `ComposeSecret` is a struct that represents a secret of a `docker-compose.yml` file.
+///
+/// It contains the following fields:
+/// * `file`: A `String` that represents the path of the file on the host that holds the secret.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ComposeSecret {
+    pub file: String,
+}
+
+impl ComposeFile {
+    /// Creates an empty compose file.
+    ///
+    /// The `version` key is not written: the Compose specification marks it obsolete. It is only kept when a merged file
+    /// declares it.
+    pub fn new() -> Self {
+        ComposeFile::default()
+    }
+
+    /// Adds the services rendered by the template of a catalog service.
+    ///
+    /// The rendered template is a YAML map of compose services, e.g. `apache:` followed by its keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `rendered` - The rendered template.
+    /// * `source` - The path of the template, used in the error messages.
+    /// * `instance` - The name of the instance the template is rendered for, if any. The compose service of the template
+    ///   is renamed after the instance, or prefixed with it when the template renders several compose services.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<String>)` - The names of the compose services added.
+    /// * `Err(RyujinError)` - If the rendered template is not valid.
+    ///
+    /// # Errors
+    ///
+    /// This function will return a `RyujinError::Template` if the rendered template is not a map of valid compose services,
+    /// or if one of its services is already defined by another template.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut compose = ComposeFile::new();
+    /// compose.add_services("  apache:\n    image: httpd:2.4\n", "template-apache.yml", None).unwrap();
+    /// ```
+    pub fn add_services(&mut self, rendered: &str, source: &str, instance: Option<&str>) -> Result<Vec<String>, RyujinError> {
+        let services: BTreeMap<String, ComposeService> = serde_yaml::from_str(rendered)
+            .map_err(|e| RyujinError::Template(format!("{} does not render valid compose services: {}", source, e)))?;
+
+        let single = services.len() == 1;
+        let mut names: Vec<String> = Vec::new();
+        for (name, service) in services {
+            let name = match instance {
+                Some(instance) if single => instance.to_string(),
+                Some(instance) => format!("{}-{}", instance, name),
+                None => name,
+            };
+            if self.services.contains_key(&name) {
+                return Err(RyujinError::Template(format!("{} renders the compose service {}, which is already defined by another service.", source, name)));
+            }
+            self.services.insert(name.clone(), service);
+            names.push(name);
+        }
+        Ok(names)
+    }
+
+    /// Declares a named volume in the top-level `volumes` section.
+    ///
+    /// Several services can share a named volume, as long as they declare it with the same driver and options.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the volume.
+    /// * `volume` - The `ComposeVolume` with the driver and its options.
+    ///
+    /// # Errors
+    ///
+    /// This function will return a `RyujinError::Input` if the volume is already declared with another driver or other options.
+    pub fn declare_volume(&mut self, name: &str, volume: ComposeVolume) -> Result<(), RyujinError> {
+        match self.volumes.get(name) {
+            Some(declared) if declared != &volume => Err(RyujinError::Input(format!("The named volume {} is used by several services with different drivers or driver options.", name))),
+            Some(_) => Ok(()),
+            None => {
+                self.volumes.insert(name.to_string(), volume);
+                Ok(())
+            },
+        }
+    }
+
+    /// Mounts a file of the host as a secret of compose services, instead of a bind mount.
+    ///
+    /// The bind mounts of the services that target the same path are removed, and the secret is declared
+    /// in the top-level `secrets` section.
+    ///
+    /// # Arguments
+    ///
+    /// * `services` - The names of the compose services.
+    /// * `name` - The name of the secret.
+    /// * `file` - The path of the file on the host.
+    /// * `target` - The path of the secret in the containers.
+    pub fn mount_secret(&mut self, services: &[String], name: &str, file: &str, target: &str) {
+        for service_name in services {
+            if let Some(service) = self.services.get_mut(service_name) {
+                // A volume is `source:target` or `source:target:mode`
+                service.volumes.retain(|volume| volume.split(':').nth(1) != Some(target));
+                service.secrets.push(ServiceSecret::Config { source: name.to_string(), target: Some(target.to_string()) });
+            }
+        }
+        self.secrets.insert(name.to_string(), ComposeSecret { file: file.to_string() });
+    }
+
+    /// Adds compose services to profiles, so they only start when one of the profiles is enabled.
+    ///
+    /// The profiles already written in the template of a service are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `services` - The names of the compose services.
+    /// * `profiles` - The names of the profiles.
+    pub fn add_profiles(&mut self, services: &[String], profiles: &[String]) {
+        for name in services {
+            if let Some(service) = self.services.get_mut(name) {
+                for profile in profiles {
+                    if !service.profiles.contains(profile) {
+                        service.profiles.push(profile.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds a group of the host to the processes of compose services, so they can read the files of that group.
+    ///
+    /// # Arguments
+    ///
+    /// * `services` - The names of the compose services.
+    /// * `group` - The ID of the group.
+    pub fn add_group(&mut self, services: &[String], group: u32) {
+        for name in services {
+            if let Some(service) = self.services.get_mut(name) {
+                if !service.group_add.contains(&group.to_string()) {
+                    service.group_add.push(group.to_string());
+                }
+            }
+        }
+    }
+
+    /// Sets the healthcheck of compose services.
+    ///
+    /// A healthcheck already written in the template of a service is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `services` - The names of the compose services.
+    /// * `healthcheck` - The `ComposeHealthcheck` to set.
+    pub fn set_healthcheck(&mut self, services: &[String], healthcheck: &ComposeHealthcheck) {
+        for name in services {
+            if let Some(service) = self.services.get_mut(name) {
+                service.healthcheck = Some(healthcheck.clone());
+            }
+        }
+    }
+
+    /// Limits the resources of compose services.
+    ///
+    /// The limits are written to `deploy.resources.limits`, and the memory and process limits to `mem_limit` and
+    /// `pids_limit` too, for the engines that ignore `deploy` outside of a swarm.
+    ///
+    /// # Arguments
+    ///
+    /// * `services` - The names of the compose services.
+    /// * `limits` - The `DeployLimits` of the containers.
+    pub fn limit_resources(&mut self, services: &[String], limits: &DeployLimits) {
+        for name in services {
+            if let Some(service) = self.services.get_mut(name) {
+                service.deploy.get_or_insert_with(Deploy::default).resources.limits = limits.clone();
+                service.mem_limit = limits.memory.clone();
+                service.pids_limit = limits.pids;
+            }
+        }
+    }
+
+    /// Adds hardening options to compose services.
+    ///
+    /// The options already written in the template of a service are kept: the lists are extended and
+    /// `user` and `read_only` are only set when the template does not set them.
+    ///
+    /// # Arguments
+    ///
+    /// * `services` - The names of the compose services.
+    /// * `hardening` - The `Hardening` options to add.
+    pub fn harden(&mut self, services: &[String], hardening: &Hardening) {
+        fn extend(list: &mut Vec<String>, values: &[String]) {
+            for value in values {
+                if !list.contains(value) {
+                    list.push(value.clone());
+                }
+            }
+        }
+
+        for name in services {
+            if let Some(service) = self.services.get_mut(name) {
+                if service.user.is_none() {
+                    service.user = hardening.user.clone();
+                }
+                if hardening.read_only && service.read_only.is_none() {
+                    service.read_only = Some(true);
+                }
+                extend(&mut service.tmpfs, &hardening.tmpfs);
+                extend(&mut service.cap_drop, &hardening.cap_drop);
+                extend(&mut service.cap_add, &hardening.cap_add);
+                extend(&mut service.security_opt, &hardening.security_opt);
+            }
+        }
+    }
+
+    /// Makes compose services start after other compose services.
+    ///
+    /// # Arguments
+    ///
+    /// * `services` - The names of the compose services that wait.
+    /// * `dependencies` - The names of the compose services started first.
+    /// * `healthy` - A `bool` that indicates whether the dependencies must be healthy (`service_healthy`), and not only started (`service_started`).
+    pub fn add_dependencies(&mut self, services: &[String], dependencies: &[String], healthy: bool) {
+        let condition = if healthy { "service_healthy" } else { "service_started" };
+        for name in services {
+            if let Some(service) = self.services.get_mut(name) {
+                for dependency in dependencies {
+                    service.depends_on.insert(dependency.clone(), ComposeDependency { condition: condition.to_string() });
+                }
+            }
+        }
+    }
+
+    /// Attaches compose services to networks, and declares the networks in the top-level `networks` section.
+    ///
+    /// # Arguments
+    ///
+    /// * `services` - The names of the compose services.
+    /// * `networks` - The names of the networks.
+    /// * `internal_networks` - The names of the networks that are cut from the outside world (`internal: true`).
+    pub fn attach_networks(&mut self, services: &[String], networks: &[String], internal_networks: &[String]) {
+        for name in services {
+            if let Some(service) = self.services.get_mut(name) {
+                for network in networks {
+                    if !service.networks.contains(network) {
+                        service.networks.push(network.clone());
+                    }
+                }
+            }
+        }
+        for network in networks {
+            let declared = self.networks.entry(network.clone()).or_default();
+            if internal_networks.contains(network) {
+                declared.internal = Some(true);
+            }
+        }
+    }
+
+    /// Serializes the compose file to YAML.
+    ///
+    /// # Errors
+    ///
+    /// This function will return a `RyujinError::Template` if the model cannot be serialized.
+    pub fn to_yaml(&self) -> Result<String, RyujinError> {
+        serde_yaml::to_string(self)
+            .map_err(|e| RyujinError::Template(format!("Unable to serialize the docker-compose: {}", e)))
+    }
+}
@@ -0,0 +1,157 @@
+use crate::generic::{Services, Service};
+use crate::instances::instances_of;
+use std::collections::HashMap;
+
+
+/// Returns the services a service starts after, among the selected services.
+///
+/// These are its `requires` and the `optional_after` that are selected too, sorted and without duplicates. A catalog
+/// service selected as several instances gives all of them.
+///
+/// # Arguments
+///
+/// * `service` - A reference to the `Service`.
+/// * `selected` - A reference to the selected `Services`.
+pub fn dependencies_of(service: &Service, selected: &Services) -> Vec<String> {
+    let mut dependencies: Vec<String> = service.requires.iter()
+        .chain(service.optional_after.iter())
+        .flat_map(|dependency| instances_of(selected, dependency))
+        .collect();
+    dependencies.sort();
+    dependencies.dedup();
+    dependencies
+}
+
+/// Returns the services required by the selected services that are not selected, under any instance name.
+///
+/// # Arguments
+///
+/// * `selected` - A reference to the selected `Services`.
+///
+/// # Returns
+///
+/// A `Vec<(String, String)>` of `(service, required service)` pairs, sorted by service name.
+pub fn missing_requirements(selected: &Services) -> Vec<(String, String)> {
+    let mut missing: Vec<(String, String)> = selected.iter()
+        .flat_map(|(name, service)| service.requires.iter()
+            .filter(|required| instances_of(selected, required).is_empty())
+            .map(move |required| (name.clone(), required.clone())))
+        .collect();
+    missing.sort();
+    missing
+}
+
+/// Visits a service for `find_cycle`, depth first.
+///
+/// The states are: absent when not visited yet, `false` while the service is on the current path, `true` once done.
+fn visit(name: &String, services: &Services, states: &mut HashMap<String, bool>, path: &mut Vec<String>) -> Option<Vec<String>> {
+    match states.get(name) {
+        Some(true) => return None,
+        Some(false) => {
+            let start = path.iter().position(|service| service == name).unwrap_or(0);
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.clone());
+            return Some(cycle);
+        },
+        None => {},
+    }
+
+    states.insert(name.clone(), false);
+    path.push(name.clone());
+    for dependency in dependencies_of(&services[name], services) {
+        if let Some(cycle) = visit(&dependency, services, states, path) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    states.insert(name.clone(), true);
+    None
+}
+
+/// Looks for a dependency cycle between services.
+///
+/// Both `requires` and `optional_after` are followed, as long as they point to one of the services given.
+///
+/// # Arguments
+///
+/// * `services` - A reference to the `Services` to check, the whole catalog or the selection.
+///
+/// # Returns
+///
+/// The services of the first cycle found, starting and ending with the same service (e.g. `["a", "b", "a"]`),
+/// or `None` if there is no cycle.
+///
+/// # Example
+///
+/// ```
+/// if let Some(cycle) = find_cycle(&selected_services) {
+///     println!("Dependency cycle: {}", cycle.join(" -> "));
+/// }
+/// ```
+pub fn find_cycle(services: &Services) -> Option<Vec<String>> {
+    let mut names: Vec<&String> = services.keys().collect();
+    names.sort();
+
+    let mut states: HashMap<String, bool> = HashMap::new();
+    for name in names {
+        if let Some(cycle) = visit(name, services, &mut states, &mut Vec::new()) {
+            return Some(cycle);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_to_string;
+
+    fn catalog() -> Services {
+        let path = format!("{}/services/services.json", env!("CARGO_MANIFEST_DIR"));
+        serde_json::from_str(&read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn selection(catalog: &Services, names: &[(&str, &str)]) -> Services {
+        names.iter().map(|(name, service)| (name.to_string(), catalog[*service].clone())).collect()
+    }
+
+    #[test]
+    fn a_requirement_is_missing_until_selected() {
+        let catalog = catalog();
+        let selected = selection(&catalog, &[("react-nginx", "react-nginx")]);
+        assert_eq!(missing_requirements(&selected), vec![("react-nginx".to_string(), "php-apache".to_string())]);
+
+        let selected = selection(&catalog, &[("react-nginx", "react-nginx"), ("php-apache", "php-apache")]);
+        assert!(missing_requirements(&selected).is_empty());
+        assert_eq!(dependencies_of(&selected["react-nginx"], &selected), vec!["php-apache"]);
+    }
+
+    #[test]
+    fn a_requirement_is_met_by_every_instance() {
+        let catalog = catalog();
+        let selected = selection(&catalog, &[("react-nginx", "react-nginx"), ("api-a", "php-apache"), ("api-b", "php-apache")]);
+        assert!(missing_requirements(&selected).is_empty());
+        assert_eq!(dependencies_of(&selected["react-nginx"], &selected), vec!["api-a", "api-b"]);
+    }
+
+    #[test]
+    fn optional_dependencies_only_count_when_selected() {
+        let catalog = catalog();
+        let selected = selection(&catalog, &[("nginx", "nginx"), ("php-apache", "php-apache")]);
+        assert!(missing_requirements(&selected).is_empty());
+        assert_eq!(dependencies_of(&selected["nginx"], &selected), vec!["php-apache"]);
+    }
+
+    #[test]
+    fn the_catalog_has_no_cycle() {
+        assert_eq!(find_cycle(&catalog()), None);
+    }
+
+    #[test]
+    fn a_cycle_is_found() {
+        let mut catalog = catalog();
+        // php-apache starting after react-nginx closes a cycle, since react-nginx requires it
+        catalog.get_mut("php-apache").unwrap().optional_after.push("react-nginx".to_string());
+        assert_eq!(find_cycle(&catalog), Some(vec!["php-apache".to_string(), "react-nginx".to_string(), "php-apache".to_string()]));
+    }
+}
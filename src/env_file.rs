@@ -0,0 +1,190 @@
+use crate::error::RyujinError;
+use crate::output::Output;
+use std::path::Path;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+
+/// `EnvEntry` is a struct that represents a variable written in the `.env` file.
+///
+/// It contains the following fields:
+/// * `name`: A `String` that represents the name of the environment variable, e.g. `DB_PASSWORD`.
+/// * `value`: A `String` that represents the value of the variable.
+/// * `comment`: A `String` written as a comment above the variable.
+#[derive(Debug, Clone)]
+pub struct EnvEntry {
+    pub name: String,
+    pub value: String,
+    pub comment: String,
+}
+
+/// Returns the name of the environment variable associated with a question variable.
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(env_variable_name("apache_container_name"), "APACHE_CONTAINER_NAME");
+/// ```
+pub fn env_variable_name(variable: &str) -> String {
+    variable.to_uppercase().replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+/// Returns the Compose interpolation that refers to an environment variable, e.g. `${DB_PASSWORD}`.
+pub fn interpolation(variable: &str) -> String {
+    format!("${{{}}}", env_variable_name(variable))
+}
+
+/// Generates a random alphanumeric value, used for the secrets the user chose not to type.
+pub fn generate_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Quotes a value for a `.env` file.
+///
+/// Values made of safe characters are written as is. Other values are single-quoted so that Compose does not
+/// interpolate them, or double-quoted with backslash escapes when they contain a single quote. In a `.env` file, `$`
+/// is escaped as `\$`: `$$` is the escape of the compose file only.
+fn quote_value(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:@".contains(c)) {
+        value.to_string()
+    } else if !value.contains('\'') {
+        format!("'{}'", value)
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "\\$"))
+    }
+}
+
+/// Removes the entries that repeat a variable, keeping the first one.
+///
+/// Two services can ask the same question, e.g. when an answers file answers it once for all of them. Their entries
+/// are written once, since they refer to the same environment variable.
+///
+/// # Arguments
+///
+/// * `entries` - The entries of the services, in the order they are written.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if two entries give different values to the same variable, since
+/// the compose file can only refer to one of them.
+///
+/// # Example
+///
+/// ```
+/// let entries = unique_entries(entries).unwrap();
+/// ```
+pub fn unique_entries(entries: Vec<EnvEntry>) -> Result<Vec<EnvEntry>, RyujinError> {
+    let mut unique: Vec<EnvEntry> = Vec::new();
+    for entry in entries {
+        match unique.iter().find(|kept| kept.name == entry.name) {
+            Some(kept) if kept.value != entry.value => {
+                return Err(RyujinError::Input(format!("The services give different values to {} in the .env file. Answer it with the same value for every service.", entry.name)));
+            },
+            Some(_) => {},
+            None => unique.push(entry),
+        }
+    }
+    Ok(unique)
+}
+
+/// Writes the `.env` file in the output directory.
+///
+/// Each entry is preceded by its comment. The file is created with `0600` permissions on Unix systems
+/// because it may contain secrets, or only rendered in a dry run.
+///
+/// When `merge` is set, the lines of an existing `.env` file are kept: the value of a variable that is written again is
+/// replaced in place, and the new variables are added at the end.
+///
+/// # Arguments
+///
+/// * `output` - A mutable reference to the `Output` the file is written to.
+/// * `output_dir` - A string slice representing the directory where the `.env` file will be written.
+/// * `entries` - A slice of `EnvEntry` to write.
+/// * `merge` - A `bool` that indicates whether the variables of an existing `.env` file are kept.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Io` if the file cannot be created or written.
+///
+/// # Example
+///
+/// ```
+/// write_env_file(&mut output, "./output", &entries, false).unwrap();
+/// ```
+pub fn write_env_file(output: &mut Output, output_dir: &str, entries: &[EnvEntry], merge: bool) -> Result<(), RyujinError> {
+    let path = Path::new(output_dir).join(".env");
+    let mut lines: Vec<String> = match output.read(&path) {
+        Ok(existing) if merge => existing.lines().map(str::to_string).collect(),
+        _ => vec!["# Generated by ryujin-cli. This file may contain secrets: do not commit it.".to_string()],
+    };
+    for entry in entries {
+        let assignment = format!("{}={}", entry.name, quote_value(&entry.value));
+        let prefix = format!("{}=", entry.name);
+        match lines.iter_mut().find(|line| line.trim_start().strip_prefix("export ").unwrap_or(line.trim_start()).starts_with(&prefix)) {
+            Some(line) => *line = assignment,
+            None => {
+                lines.push(String::new());
+                lines.extend(entry.comment.lines().map(|line| format!("# {}", line)));
+                lines.push(assignment);
+            },
+        }
+    }
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    output.write(&path, &content, Some(0o600))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads a value of a `.env` file like Compose does, failing on a `$` that Compose would interpolate.
+    fn read_value(quoted: &str) -> String {
+        if let Some(inner) = quoted.strip_prefix('\'').and_then(|rest| rest.strip_suffix('\'')) {
+            return inner.to_string();
+        }
+        let Some(inner) = quoted.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) else {
+            assert!(!quoted.contains('$'), "{} would be interpolated", quoted);
+            return quoted.to_string();
+        };
+        let mut value = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => value.push(chars.next().expect("An escape is followed by a character")),
+                '$' => panic!("{} would be interpolated", quoted),
+                '"' => panic!("{} ends before its closing quote", quoted),
+                c => value.push(c),
+            }
+        }
+        value
+    }
+
+    #[test]
+    fn quoted_values_are_read_back_unchanged() {
+        let values = [
+            "plain_value-1.2:3@host/path",
+            "with spaces and $HOME",
+            "it's",
+            "it's $HOME and ${USER}",
+            "a'$$b",
+            "quote \" and backslash \\ and '",
+            "\\$'",
+        ];
+        for value in values {
+            assert_eq!(read_value(&quote_value(value)), value, "quoted as {}", quote_value(value));
+        }
+    }
+
+    #[test]
+    fn generated_secrets_need_no_quotes() {
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert_eq!(quote_value(&secret), secret);
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+
+
+/// `RyujinError` is the error type returned by every subcommand.
+///
+/// Each variant separates a family of failures and maps to a documented exit code, so scripts wrapping
+/// ryujin-cli can tell what went wrong:
+///
+/// | Variant     | Exit code | Meaning                                                                 |
+/// |-------------|-----------|-------------------------------------------------------------------------|
+/// | `Input`     | 2         | Invalid user input: unknown service, bad answer, unanswered question... |
+/// | `Catalog`   | 3         | The catalog (`services.json` and its templates) is missing or invalid.  |
+/// | `Selection` | 4         | The selection configuration (`conf/conf.json`) is missing or invalid.   |
+/// | `Template`  | 5         | A template could not be parsed or rendered.                             |
+/// | `Io`        | 6         | A file or directory could not be read or written.                       |
+/// | `Findings`  | 7         | The checked compose file is invalid, or has risky settings: `validate`  |
+/// |             |           | found problems, or `audit` found findings at or above `--fail-on`.      |
+///
+/// Exit code 1 is left to unexpected failures (panics) and 0 means success.
+#[derive(Debug)]
+pub enum RyujinError {
+    Input(String),
+    Catalog(String),
+    Selection(String),
+    Template(String),
+    Io(std::io::Error),
+    Findings(String),
+}
+
+impl RyujinError {
+    /// Returns the exit code of the process for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RyujinError::Input(_) => 2,
+            RyujinError::Catalog(_) => 3,
+            RyujinError::Selection(_) => 4,
+            RyujinError::Template(_) => 5,
+            RyujinError::Io(_) => 6,
+            RyujinError::Findings(_) => 7,
+        }
+    }
+}
+
+impl fmt::Display for RyujinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RyujinError::Input(message) => write!(f, "Invalid input: {}", message),
+            RyujinError::Catalog(message) => write!(f, "Catalog error: {}", message),
+            RyujinError::Selection(message) => write!(f, "Selection error: {}", message),
+            RyujinError::Template(message) => write!(f, "Template error: {}", message),
+            RyujinError::Io(error) => write!(f, "I/O error: {}", error),
+            RyujinError::Findings(message) => write!(f, "Check failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RyujinError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RyujinError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RyujinError {
+    fn from(error: std::io::Error) -> Self {
+        RyujinError::Io(error)
+    }
+}
+
+impl From<tera::Error> for RyujinError {
+    fn from(error: tera::Error) -> Self {
+        // Tera nests the real cause (unknown variable, syntax error...) in the source chain
+        let mut message = error.to_string();
+        let mut source = std::error::Error::source(&error);
+        while let Some(cause) = source {
+            message.push_str(&format!(": {}", cause));
+            source = cause.source();
+        }
+        RyujinError::Template(message)
+    }
+}
@@ -0,0 +1,130 @@
+use crate::generic::{Services, Healthcheck};
+use crate::error::RyujinError;
+use regex::Regex;
+
+
+/// The settings of a healthcheck that can be tuned with `--healthcheck`.
+pub const SETTINGS: [&str; 5] = ["test", "interval", "timeout", "retries", "start_period"];
+
+/// `HealthcheckTuning` is a struct that represents a setting of a healthcheck given on the command line.
+///
+/// It contains the following fields:
+/// * `service`: A `String` that represents the name of the service.
+/// * `setting`: A `String` that represents the setting of the healthcheck, one of `SETTINGS`.
+/// * `value`: A `String` that represents the new value of the setting.
+#[derive(Debug, Clone)]
+pub struct HealthcheckTuning {
+    pub service: String,
+    pub setting: String,
+    pub value: String,
+}
+
+/// Tells whether a value is a compose duration, e.g. `30s`, `1m30s` or `500ms`.
+pub fn is_duration(value: &str) -> bool {
+    let duration = Regex::new(r"^([0-9]+(\.[0-9]+)?(h|m|s|ms|us|ns))+$").expect("The duration pattern is valid");
+    duration.is_match(value)
+}
+
+/// Checks the value of a healthcheck setting.
+fn check_setting(setting: &str, value: &str) -> Result<(), String> {
+    match setting {
+        "test" if value.trim().is_empty() => Err("the test command is empty".to_string()),
+        "retries" => value.parse::<u32>().map(|_| ()).map_err(|_| format!("{} is not a number of retries", value)),
+        "interval" | "timeout" | "start_period" if !is_duration(value) => Err(format!("{} is not a duration, e.g. 30s or 1m30s", value)),
+        "test" | "interval" | "timeout" | "start_period" => Ok(()),
+        _ => Err(format!("{} is not a healthcheck setting, use one of {}", setting, SETTINGS.join(", "))),
+    }
+}
+
+/// Parses a healthcheck setting given as `<service>.<setting>=<value>`, e.g. `nginx.interval=10s`.
+///
+/// # Arguments
+///
+/// * `value` - The value of the `--healthcheck` option.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if the value does not follow the `<service>.<setting>=<value>` format,
+/// if the setting is unknown or if its value is not valid (a duration for `interval`, `timeout` and `start_period`,
+/// a number for `retries`).
+///
+/// # Example
+///
+/// ```
+/// let tuning = parse_healthcheck_tuning("nginx.interval=10s").unwrap();
+/// ```
+pub fn parse_healthcheck_tuning(value: &str) -> Result<HealthcheckTuning, RyujinError> {
+    let invalid = || RyujinError::Input(format!("Invalid healthcheck setting {}. Use <service>.<setting>=<value>, e.g. nginx.interval=10s.", value));
+
+    let (target, setting_value) = value.split_once('=').ok_or_else(invalid)?;
+    let (service, setting) = target.rsplit_once('.').ok_or_else(invalid)?;
+    let (service, setting, setting_value) = (service.trim(), setting.trim(), setting_value.trim());
+    check_setting(setting, setting_value)
+        .map_err(|e| RyujinError::Input(format!("Invalid healthcheck setting {}: {}.", value, e)))?;
+
+    Ok(HealthcheckTuning { service: service.to_string(), setting: setting.to_string(), value: setting_value.to_string() })
+}
+
+/// Changes a setting of a healthcheck. A `test` given on the command line is run with the shell of the container.
+fn set_setting(healthcheck: &mut Healthcheck, setting: &str, value: &str) {
+    match setting {
+        "test" => healthcheck.test = vec!["CMD-SHELL".to_string(), value.to_string()],
+        "interval" => healthcheck.interval = Some(value.to_string()),
+        "timeout" => healthcheck.timeout = Some(value.to_string()),
+        "retries" => healthcheck.retries = value.parse().ok(),
+        "start_period" => healthcheck.start_period = Some(value.to_string()),
+        _ => {},
+    }
+}
+
+/// Applies the healthcheck options of the command line to the selected services.
+///
+/// The settings given with `--healthcheck` are applied first. A service without healthcheck in the catalog gets one,
+/// as long as its `test` is given. Then the healthchecks of the services given with `--no-healthcheck` are removed.
+///
+/// # Arguments
+///
+/// * `services` - A mutable reference to the selected `Services`.
+/// * `disabled` - The services given with `--no-healthcheck`: `None` when the option is not used, an empty slice to disable every healthcheck.
+/// * `tunings` - A slice of `HealthcheckTuning` parsed from the `--healthcheck` options.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if an option targets a service that is not selected, if a service is
+/// both tuned and disabled, or if a service gets a healthcheck without `test`.
+///
+/// # Example
+///
+/// ```
+/// configure_healthchecks(&mut selected_services, None, &[parse_healthcheck_tuning("nginx.retries=5").unwrap()]).unwrap();
+/// ```
+pub fn configure_healthchecks(services: &mut Services, disabled: Option<&[String]>, tunings: &[HealthcheckTuning]) -> Result<(), RyujinError> {
+    for tuning in tunings {
+        if disabled.is_some_and(|disabled| disabled.is_empty() || disabled.contains(&tuning.service)) {
+            return Err(RyujinError::Input(format!("Cannot set {}.{}: the healthcheck of {} is disabled with --no-healthcheck.", tuning.service, tuning.setting, tuning.service)));
+        }
+        let service = services.get_mut(&tuning.service)
+            .ok_or_else(|| RyujinError::Input(format!("Cannot set {}.{}: the service {} is not selected.", tuning.service, tuning.setting, tuning.service)))?;
+        set_setting(service.healthcheck.get_or_insert_with(Healthcheck::default), &tuning.setting, &tuning.value);
+    }
+
+    for (name, service) in services.iter() {
+        if service.healthcheck.as_ref().is_some_and(|healthcheck| healthcheck.test.is_empty()) {
+            return Err(RyujinError::Input(format!("The service {} has no healthcheck in the catalog. Give its command with --healthcheck {}.test=<command>.", name, name)));
+        }
+    }
+
+    match disabled {
+        Some([]) => services.values_mut().for_each(|service| service.healthcheck = None),
+        Some(names) => {
+            for name in names {
+                let service = services.get_mut(name)
+                    .ok_or_else(|| RyujinError::Input(format!("Cannot disable the healthcheck of {}: the service is not selected.", name)))?;
+                service.healthcheck = None;
+            }
+        },
+        None => {},
+    }
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use crate::generic::{Services, Service};
+use crate::error::RyujinError;
+use crate::env_file::env_variable_name;
+use regex::Regex;
+
+
+/// Parses a service of the selection given as `<service>` or `<service>:<instance>`, e.g. `nginx:site-a`.
+///
+/// An instance is a copy of a catalog service with its own name, so the same service can be selected several times.
+///
+/// # Arguments
+///
+/// * `choice` - A service of the selection, without its profiles.
+///
+/// # Returns
+///
+/// * `Ok((String, Option<String>))` - The name of the catalog service and the name of the instance, if any.
+/// * `Err(RyujinError)` - If the name of the instance is not valid.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if the name of the instance is empty or is not a valid compose
+/// service name: lowercase letters, digits, `_`, `.` and `-`, starting with a letter or a digit.
+///
+/// # Example
+///
+/// ```
+/// let (service, instance) = parse_instance("nginx:site-a").unwrap();
+/// ```
+pub fn parse_instance(choice: &str) -> Result<(String, Option<String>), RyujinError> {
+    let Some((service, instance)) = choice.split_once(':') else {
+        return Ok((choice.to_string(), None));
+    };
+
+    let instance_name = Regex::new(r"^[a-z0-9][a-z0-9_.-]*$").expect("The instance pattern is valid");
+    let instance = instance.trim();
+    if !instance_name.is_match(instance) {
+        return Err(RyujinError::Input(format!("Invalid instance {:?} in {}. Use <service>:<instance>, e.g. nginx:site-a, with instance names made of lowercase letters, digits, _, . and -.", instance, choice)));
+    }
+
+    Ok((service.trim().to_string(), Some(instance.to_string())))
+}
+
+/// Checks that a new name of the selection does not collide with a selected service once it prefixes `.env` variables.
+///
+/// The `.env` variables of an instance are prefixed with its name in upper case, `-` and `.` becoming `_`, so
+/// `site-a` and `site_a` would overwrite the variables of each other.
+///
+/// # Arguments
+///
+/// * `selected` - A reference to the `Services` selected so far.
+/// * `key` - The name of the service or instance being added to the selection.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if another selected service has the same name once normalized.
+pub fn check_instance_name(selected: &Services, key: &str) -> Result<(), RyujinError> {
+    let prefix = env_variable_name(key);
+    match selected.keys().find(|other| *other != key && env_variable_name(other) == prefix) {
+        Some(other) => Err(RyujinError::Input(format!("The names {} and {} cannot be used together: both give the {}_ prefix to the .env variables. Rename one of them.", other, key, prefix))),
+        None => Ok(()),
+    }
+}
+
+/// Tells whether a selected service is an instance, i.e. was selected under another name than its catalog name.
+///
+/// # Arguments
+///
+/// * `name` - The name of the service in the selection.
+/// * `service` - A reference to the `Service`.
+pub fn is_instance(name: &str, service: &Service) -> bool {
+    name != service.name
+}
+
+/// Returns a variable of a selected service in the namespace of its instance, e.g. `site-a_certificate_path`.
+///
+/// The variables of a service selected under its catalog name are returned as is. This is used for the names that
+/// must be unique in the whole docker-compose, such as the `.env` variables and the secrets.
+///
+/// # Arguments
+///
+/// * `name` - The name of the service in the selection.
+/// * `service` - A reference to the `Service`.
+/// * `variable` - The variable of the service.
+pub fn namespaced(name: &str, service: &Service, variable: &str) -> String {
+    if is_instance(name, service) {
+        format!("{}_{}", name, variable)
+    } else {
+        variable.to_string()
+    }
+}
+
+/// Returns the names of the selected services that are instances of a catalog service, sorted.
+///
+/// The dependencies of the catalog (`requires`, `optional_after`) refer to catalog services, and are met by any of
+/// their instances.
+///
+/// # Arguments
+///
+/// * `selected` - A reference to the selected `Services`.
+/// * `service_name` - The name of the catalog service.
+pub fn instances_of(selected: &Services, service_name: &str) -> Vec<String> {
+    let mut names: Vec<String> = selected.iter()
+        .filter(|(_, service)| service.name == service_name)
+        .map(|(name, _)| name.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs::read_to_string;
+
+    fn catalog() -> Services {
+        let path = format!("{}/services/services.json", env!("CARGO_MANIFEST_DIR"));
+        serde_json::from_str(&read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn instances_are_parsed() {
+        assert_eq!(parse_instance("nginx").unwrap(), ("nginx".to_string(), None));
+        assert_eq!(parse_instance("nginx:site-a").unwrap(), ("nginx".to_string(), Some("site-a".to_string())));
+        for choice in ["nginx:", "nginx:Site", "nginx:-site", "nginx:site a"] {
+            assert!(matches!(parse_instance(choice), Err(RyujinError::Input(_))), "{}", choice);
+        }
+    }
+
+    #[test]
+    fn names_colliding_in_the_env_file_are_rejected() {
+        let catalog = catalog();
+        let selected: Services = HashMap::from([("site-a".to_string(), catalog["nginx"].clone())]);
+
+        assert!(check_instance_name(&selected, "site-a").is_ok());
+        assert!(check_instance_name(&selected, "site-b").is_ok());
+        for key in ["site_a", "site.a"] {
+            let error = check_instance_name(&selected, key).unwrap_err();
+            assert!(error.to_string().contains("site-a and "), "{}", error);
+        }
+        assert_eq!(namespaced("site-a", &catalog["nginx"], "certificate_path"), "site-a_certificate_path");
+        assert_eq!(namespaced("nginx", &catalog["nginx"], "certificate_path"), "certificate_path");
+    }
+}
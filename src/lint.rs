@@ -0,0 +1,515 @@
+use crate::generic::{Services, QuestionKind, SecurityOption, Size, ryujin_dir};
+use crate::error::RyujinError;
+use crate::healthcheck::is_duration;
+use crate::dependencies::find_cycle;
+use crate::resources::{is_cpus, is_memory};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::path::Path;
+
+
+/// Variables provided by the compose generator to every service template, that the catalog does not declare.
+const BUILTIN_VARIABLES: [&str; 2] = ["skipped", "ports"];
+
+/// Words of the Tera language that look like variables in an expression.
+const TERA_KEYWORDS: [&str; 16] = [
+    "if", "elif", "else", "endif", "for", "endfor", "in", "not", "and", "or",
+    "is", "true", "false", "set", "loop", "defined",
+];
+
+/// `Diagnostic` is a struct that represents a problem found in the catalog.
+///
+/// It contains the following fields:
+/// * `location`: A `String` that represents where the problem is, e.g. `services/services.json: apache` or `template-apache.yml:3`.
+/// * `message`: A `String` that describes the problem.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub location: String,
+    pub message: String,
+}
+
+/// Extracts the variables used by a Tera template, with the line where each one is first used.
+///
+/// The function looks at the `{{ ... }}` expressions and at the `{% if %}`, `{% elif %}` and `{% for %}` tags.
+/// String literals, filters, tests, attributes and the variables bound by `{% for %}` and `{% set %}` are ignored.
+///
+/// # Arguments
+///
+/// * `template` - The content of the template.
+///
+/// # Example
+///
+/// ```
+/// let variables = template_variables("container_name: {{ apache_container_name }}");
+/// ```
+fn template_variables(template: &str) -> Vec<(String, usize)> {
+    let tag = Regex::new(r"(?s)\{\{-?(.*?)-?\}\}|\{%-?(.*?)-?%\}").expect("The tag pattern is valid");
+    let literal = Regex::new(r#""[^"]*"|'[^']*'"#).expect("The literal pattern is valid");
+    let filter = Regex::new(r"\|\s*[A-Za-z_][A-Za-z0-9_]*").expect("The filter pattern is valid");
+    let test = Regex::new(r"\bis\s+(not\s+)?[A-Za-z_][A-Za-z0-9_]*").expect("The test pattern is valid");
+    let binding = Regex::new(r"^\s*(?:for\s+([A-Za-z_][A-Za-z0-9_]*)(?:\s*,\s*([A-Za-z_][A-Za-z0-9_]*))?\s+in|set\s+([A-Za-z_][A-Za-z0-9_]*)\s*=)").expect("The binding pattern is valid");
+    let identifier = Regex::new(r"(^|[^.A-Za-z0-9_])([A-Za-z_][A-Za-z0-9_]*)").expect("The identifier pattern is valid");
+
+    let mut locals: HashSet<String> = HashSet::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut variables: Vec<(String, usize)> = Vec::new();
+
+    for captures in tag.captures_iter(template) {
+        let whole = captures.get(0).expect("A match has a whole capture");
+        let line = template[..whole.start()].matches('\n').count() + 1;
+        let (mut expression, is_statement) = match (captures.get(1), captures.get(2)) {
+            (Some(expression), _) => (expression.as_str().to_string(), false),
+            (_, Some(statement)) => (statement.as_str().to_string(), true),
+            _ => continue,
+        };
+
+        if is_statement {
+            let keyword = expression.split_whitespace().next().unwrap_or("");
+            if !["if", "elif", "for", "set"].contains(&keyword) {
+                continue;
+            }
+            if let Some(bound) = binding.captures(&expression) {
+                for index in 1..=3 {
+                    if let Some(name) = bound.get(index) {
+                        locals.insert(name.as_str().to_string());
+                    }
+                }
+            }
+            // Only keep the iterated expression of a for loop, and the value of a set
+            if keyword == "for" {
+                expression = expression.split_once(" in ").map(|(_, rest)| rest.to_string()).unwrap_or_default();
+            } else if keyword == "set" {
+                expression = expression.split_once('=').map(|(_, rest)| rest.to_string()).unwrap_or_default();
+            }
+        }
+
+        let expression = literal.replace_all(&expression, " ");
+        let expression = filter.replace_all(&expression, " ");
+        let expression = test.replace_all(&expression, " ");
+        for captures in identifier.captures_iter(&expression) {
+            let name = captures[2].to_string();
+            if TERA_KEYWORDS.contains(&name.as_str()) || locals.contains(&name) || !seen.insert(name.clone()) {
+                continue;
+            }
+            variables.push((name, line));
+        }
+    }
+
+    variables
+}
+
+/// Finds the lines of a service template that render a `{{ ... }}` expression outside a double-quoted YAML scalar.
+///
+/// The compose generator escapes the rendered values for double-quoted scalars only, so an unquoted answer such as
+/// `/srv/my #site` would be cut short.
+///
+/// # Arguments
+///
+/// * `template` - The content of the template.
+fn unquoted_expressions(template: &str) -> Vec<usize> {
+    template.lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            line.match_indices("{{").any(|(start, _)| {
+                let before = &line[..start];
+                (before.matches('"').count() - before.matches("\\\"").count()) % 2 == 0
+            })
+        })
+        .map(|(index, _)| index + 1)
+        .collect()
+}
+
+/// Extracts the compose service keys emitted by a service template.
+///
+/// Service templates are rendered under the `services:` section, so the keys are the lines indented with two spaces.
+///
+/// # Arguments
+///
+/// * `template` - The content of the template.
+fn template_service_keys(template: &str) -> Vec<(String, usize)> {
+    let key = Regex::new(r"^  ([A-Za-z0-9_.-]+):\s*$").expect("The service key pattern is valid");
+    template.lines()
+        .enumerate()
+        .filter_map(|(index, line)| key.captures(line).map(|captures| (captures[1].to_string(), index + 1)))
+        .collect()
+}
+
+/// Checks the consistency of the catalog and its templates.
+///
+/// The following checks are run for every service of the catalog:
+/// * the key of the service in `services.json` matches `Service.name`;
+/// * the `template_path` exists;
+/// * the readme partials (`<name>-information.md` and `<name>-configuration.md`) exist;
+/// * every entry in `variables` has a question, and every question is listed in `variables`;
+/// * every `enum` question has choices, and only `volume` questions have driver options;
+/// * every network name is a valid compose network name;
+/// * every secret file refers to a `file` or `host_path` question and has an absolute target;
+/// * the `tls` certificate and key refer to `file` questions, the key being asked after the certificate;
+/// * every healthcheck test starts with `CMD`, `CMD-SHELL` or `NONE`, and its durations are valid;
+/// * every service in `requires` and `optional_after` is in the catalog and is not the service itself;
+/// * every service recommends valid resource limits for the `small`, `medium` and `large` presets;
+/// * every capability of `security.cap_add` is a capability name, every `security.tmpfs` is an absolute path, and the
+///   services that do not opt out of `user` and `read_only` declare a `user` and no `tmpfs` is declared in vain;
+/// * every port has a unique name and either a fixed `host` port or the `variable` of a `port` question;
+/// * every Tera variable used in the template is declared in `variables`, and rendered inside a double-quoted value;
+/// * no two templates emit the same compose service key;
+/// * the services do not depend on each other in a cycle.
+///
+/// # Arguments
+///
+/// * `services` - A reference to the `Services` of the catalog.
+/// * `dir_path` - The path to the ryujin-cli directory, used as the base path of the templates.
+///
+/// # Returns
+///
+/// A `Vec<Diagnostic>` with every problem found, sorted by location.
+pub fn lint_catalog(services: &Services, dir_path: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut service_keys: HashMap<String, String> = HashMap::new();
+
+    let network_name = Regex::new(r"^[a-zA-Z0-9._-]+$").expect("The network name pattern is valid");
+    let capability_name = Regex::new(r"^[A-Z][A-Z_]*$").expect("The capability name pattern is valid");
+
+    let mut names: Vec<&String> = services.keys().collect();
+    names.sort();
+
+    for key in names {
+        let service = &services[key];
+        let location = format!("services/services.json: {}", key);
+        let mut report = |location: &str, message: String| diagnostics.push(Diagnostic { location: location.to_string(), message });
+
+        if &service.name != key {
+            report(&location, format!("the key {} does not match the service name {}", key, service.name));
+        }
+
+        // The readme partials are read by the readme generator
+        for partial in ["information", "configuration"] {
+            let partial_path = format!("services/templates/readme/partials/{}-{}.md", key, partial);
+            if !Path::new(dir_path).join(&partial_path).is_file() {
+                report(&location, format!("the readme partial {} does not exist", partial_path));
+            }
+        }
+
+        let questions: HashSet<&String> = service.questions.iter().map(|question| &question.variable).collect();
+        for variable in &service.variables {
+            if !questions.contains(variable) {
+                report(&location, format!("the variable {} has no question", variable));
+            }
+        }
+        for question in &service.questions {
+            if !service.variables.contains(&question.variable) {
+                report(&location, format!("the question {} is not listed in variables", question.variable));
+            }
+            if question.kind == QuestionKind::Enum && question.choices.is_empty() {
+                report(&location, format!("the enum question {} has no choices", question.variable));
+            }
+            if question.kind != QuestionKind::Volume && (question.driver.is_some() || !question.driver_opts.is_empty()) {
+                report(&location, format!("the question {} has volume driver options but is not a volume question", question.variable));
+            }
+        }
+
+        for network in &service.networks {
+            if !network_name.is_match(network) {
+                report(&location, format!("the network {} is not a valid network name", network));
+            }
+        }
+
+        for secret_file in &service.secret_files {
+            let is_file = service.questions.iter()
+                .any(|question| question.variable == secret_file.variable && matches!(question.kind, QuestionKind::File | QuestionKind::HostPath));
+            if !is_file {
+                report(&location, format!("the secret file {} is not a file or host_path question", secret_file.variable));
+            }
+            if !secret_file.target.starts_with('/') {
+                report(&location, format!("the target {} of the secret file {} is not an absolute path", secret_file.target, secret_file.variable));
+            }
+        }
+
+        if let Some(tls) = &service.tls {
+            let position = |variable: &str| service.questions.iter()
+                .position(|question| question.variable == variable && matches!(question.kind, QuestionKind::File));
+            match (position(&tls.certificate), position(&tls.key)) {
+                (None, _) => report(&location, format!("the TLS certificate {} is not a file question", tls.certificate)),
+                (_, None) => report(&location, format!("the TLS key {} is not a file question", tls.key)),
+                (Some(certificate), Some(key)) if key < certificate => report(&location, format!("the TLS key {} must be asked after the certificate {}", tls.key, tls.certificate)),
+                _ => {},
+            }
+        }
+
+        if let Some(healthcheck) = &service.healthcheck {
+            if !matches!(healthcheck.test.first().map(String::as_str), Some("CMD" | "CMD-SHELL" | "NONE")) {
+                report(&location, "the healthcheck test must start with CMD, CMD-SHELL or NONE".to_string());
+            }
+            for (setting, value) in [("interval", &healthcheck.interval), ("timeout", &healthcheck.timeout), ("start_period", &healthcheck.start_period)] {
+                if let Some(value) = value.as_ref().filter(|value| !is_duration(value)) {
+                    report(&location, format!("the healthcheck {} {} is not a duration", setting, value));
+                }
+            }
+        }
+
+        for dependency in service.requires.iter().chain(service.optional_after.iter()) {
+            if dependency == key {
+                report(&location, "the service depends on itself".to_string());
+            } else if !services.contains_key(dependency) {
+                report(&location, format!("the dependency {} is not in the catalog", dependency));
+            }
+        }
+
+        for size in [Size::Small, Size::Medium, Size::Large] {
+            match service.resources.get(&size) {
+                Some(limits) => {
+                    if let Some(cpus) = limits.cpus.as_ref().filter(|cpus| !is_cpus(cpus)) {
+                        report(&location, format!("the {} cpus limit {} is not a number of CPUs", size, cpus));
+                    }
+                    if let Some(memory) = limits.memory.as_ref().filter(|memory| !is_memory(memory)) {
+                        report(&location, format!("the {} memory limit {} is not an amount of memory", size, memory));
+                    }
+                    if limits.pids == Some(0) {
+                        report(&location, format!("the {} pids limit must be greater than 0", size));
+                    }
+                },
+                None => report(&location, format!("the resource limits of the {} preset are missing", size)),
+            }
+        }
+
+        let security = &service.security;
+        for capability in &security.cap_add {
+            if !capability_name.is_match(capability) {
+                report(&location, format!("the capability {} is not a capability name, e.g. NET_BIND_SERVICE", capability));
+            }
+        }
+        for directory in &security.tmpfs {
+            if !directory.starts_with('/') {
+                report(&location, format!("the tmpfs {} is not an absolute path", directory));
+            }
+        }
+        if security.user.is_none() && !security.opt_out.contains(&SecurityOption::User) {
+            report(&location, "the service has no security user and does not opt out of user".to_string());
+        }
+        if !security.tmpfs.is_empty() && security.opt_out.contains(&SecurityOption::ReadOnly) {
+            report(&location, "the service opts out of read_only, its tmpfs are never mounted".to_string());
+        }
+
+        let mut port_names: HashSet<&String> = HashSet::new();
+        for mapping in &service.ports {
+            if !port_names.insert(&mapping.name) {
+                report(&location, format!("the port {} is declared twice", mapping.name));
+            }
+            match (&mapping.host, &mapping.variable) {
+                (Some(_), None) => {},
+                (None, Some(variable)) => {
+                    let is_port = service.questions.iter().any(|question| &question.variable == variable && question.kind == QuestionKind::Port);
+                    if !is_port {
+                        report(&location, format!("the port {} refers to {}, which is not a port question", mapping.name, variable));
+                    }
+                },
+                _ => report(&location, format!("the port {} must have either a host port or a variable", mapping.name)),
+            }
+        }
+
+        let template = match read_to_string(Path::new(dir_path).join(&service.template_path)) {
+            Ok(template) => template,
+            Err(_) => {
+                report(&location, format!("the template_path {} does not exist", service.template_path));
+                continue;
+            },
+        };
+
+        for (variable, line) in template_variables(&template) {
+            if !service.variables.contains(&variable) && !BUILTIN_VARIABLES.contains(&variable.as_str()) {
+                report(&format!("{}:{}", service.template_path, line), format!("the variable {} is not declared in the variables of {}", variable, key));
+            }
+        }
+
+        for line in unquoted_expressions(&template) {
+            report(&format!("{}:{}", service.template_path, line), "the expression must be inside a double-quoted value, e.g. \"{{ variable }}:/data\"".to_string());
+        }
+
+        for (service_key, line) in template_service_keys(&template) {
+            match service_keys.get(&service_key) {
+                Some(other) => report(&format!("{}:{}", service.template_path, line), format!("the compose service key {} is already emitted by {}", service_key, other)),
+                None => { service_keys.insert(service_key, key.clone()); },
+            }
+        }
+    }
+
+    if let Some(cycle) = find_cycle(services) {
+        diagnostics.push(Diagnostic { location: "services/services.json".to_string(), message: format!("the services depend on each other in a cycle: {}", cycle.join(" -> ")) });
+    }
+
+    // The sort is stable, so the problems of a location stay in the order of the checks
+    diagnostics.sort_by_key(|diagnostic| location_key(&diagnostic.location));
+    diagnostics
+}
+
+/// Returns the key the diagnostics are sorted by: the file, then the line as a number (`template.yml:9` comes before
+/// `template.yml:10`), then the rest of the location, e.g. the name of the service.
+fn location_key(location: &str) -> (String, usize, String) {
+    let (file, rest) = location.split_once(':').unwrap_or((location, ""));
+    match rest.parse::<usize>() {
+        Ok(line) => (file.to_string(), line, String::new()),
+        Err(_) => (file.to_string(), 0, rest.trim().to_string()),
+    }
+}
+
+/// Lints the catalog and prints the problems found.
+///
+/// # Arguments
+///
+/// * `services` - A reference to the `Services` of the catalog.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Catalog` if at least one problem was found.
+///
+/// # Example
+///
+/// ```
+/// let services = load_services();
+/// lint::handle(&services).unwrap();
+/// ```
+pub fn handle(services: &Services) -> Result<(), RyujinError> {
+    // get the path to the ryujin-cli directory
+    // it is used as the base path to the templates
+    let dir_path = ryujin_dir()?;
+
+    let diagnostics = lint_catalog(services, &dir_path);
+    for diagnostic in &diagnostics {
+        println!("error: {}: {}", diagnostic.location, diagnostic.message);
+    }
+
+    if diagnostics.is_empty() {
+        println!("The catalog is consistent ({} services checked).", services.len());
+        Ok(())
+    } else {
+        Err(RyujinError::Catalog(format!("{} problem(s) found in the catalog.", diagnostics.len())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations_are_sorted_by_file_then_line() {
+        let mut locations = vec![
+            "services/templates/compose/template-nginx.yml:10",
+            "services/services.json: nginx",
+            "services/templates/compose/template-nginx.yml:9",
+            "services/services.json",
+            "services/services.json: apache",
+            "services/templates/compose/template-apache.yml:3",
+        ];
+        locations.sort_by_key(|location| location_key(location));
+
+        assert_eq!(locations, vec![
+            "services/services.json",
+            "services/services.json: apache",
+            "services/services.json: nginx",
+            "services/templates/compose/template-apache.yml:3",
+            "services/templates/compose/template-nginx.yml:9",
+            "services/templates/compose/template-nginx.yml:10",
+        ]);
+    }
+
+    #[test]
+    fn unquoted_expressions_are_found() {
+        let template = "  web:\n    container_name: {{ name }}\n    volumes:\n      - \"{{ data }}:/data\"\n      - /srv:/srv\n      - \"\\\"\" {{ path }}\n";
+        assert_eq!(unquoted_expressions(template), vec![2, 6]);
+    }
+
+    fn catalog() -> Services {
+        let path = format!("{}/services/services.json", env!("CARGO_MANIFEST_DIR"));
+        serde_json::from_str(&read_to_string(path).unwrap()).unwrap()
+    }
+
+    /// Lints the catalog and returns the messages reported at a location.
+    fn problems(services: &Services, location: &str) -> Vec<String> {
+        lint_catalog(services, env!("CARGO_MANIFEST_DIR")).into_iter()
+            .filter(|diagnostic| diagnostic.location == location)
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    }
+
+    #[test]
+    fn the_catalog_is_consistent() {
+        assert!(lint_catalog(&catalog(), env!("CARGO_MANIFEST_DIR")).is_empty());
+    }
+
+    #[test]
+    fn an_invalid_healthcheck_is_reported() {
+        let mut services = catalog();
+        let healthcheck = services.get_mut("nginx").unwrap().healthcheck.as_mut().unwrap();
+        healthcheck.test = vec!["curl -f http://localhost/".to_string()];
+        healthcheck.interval = Some("30 seconds".to_string());
+
+        assert_eq!(problems(&services, "services/services.json: nginx"), vec![
+            "the healthcheck test must start with CMD, CMD-SHELL or NONE",
+            "the healthcheck interval 30 seconds is not a duration",
+        ]);
+    }
+
+    #[test]
+    fn invalid_resource_limits_are_reported() {
+        let mut services = catalog();
+        let resources = &mut services.get_mut("nginx").unwrap().resources;
+        let small = resources.get_mut(&Size::Small).unwrap();
+        small.cpus = Some("0".to_string());
+        small.memory = Some("1t".to_string());
+        small.pids = Some(0);
+        resources.remove(&Size::Large);
+
+        assert_eq!(problems(&services, "services/services.json: nginx"), vec![
+            "the small cpus limit 0 is not a number of CPUs",
+            "the small memory limit 1t is not an amount of memory",
+            "the small pids limit must be greater than 0",
+            "the resource limits of the large preset are missing",
+        ]);
+    }
+
+    #[test]
+    fn invalid_security_settings_are_reported() {
+        let mut services = catalog();
+        let security = &mut services.get_mut("nginx").unwrap().security;
+        security.cap_add.push("net_admin".to_string());
+        security.tmpfs.push("run".to_string());
+        security.user = None;
+        services.get_mut("pwn").unwrap().security.tmpfs.push("/tmp".to_string());
+
+        assert_eq!(problems(&services, "services/services.json: nginx"), vec![
+            "the capability net_admin is not a capability name, e.g. NET_BIND_SERVICE",
+            "the tmpfs run is not an absolute path",
+            "the service has no security user and does not opt out of user",
+        ]);
+        assert_eq!(problems(&services, "services/services.json: pwn"), vec![
+            "the service opts out of read_only, its tmpfs are never mounted",
+        ]);
+    }
+
+    #[test]
+    fn invalid_dependencies_are_reported() {
+        let mut services = catalog();
+        services.get_mut("pwn").unwrap().requires = vec!["mysql".to_string(), "pwn".to_string()];
+
+        assert_eq!(problems(&services, "services/services.json: pwn"), vec![
+            "the dependency mysql is not in the catalog",
+            "the service depends on itself",
+        ]);
+    }
+
+    #[test]
+    fn a_dependency_cycle_is_reported() {
+        let mut services = catalog();
+        services.get_mut("php-apache").unwrap().requires.push("nginx".to_string());
+        services.get_mut("nginx").unwrap().optional_after.clear();
+        services.get_mut("nginx").unwrap().requires.push("react-nginx".to_string());
+
+        let problems = problems(&services, "services/services.json");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("the services depend on each other in a cycle: "), "{}", problems[0]);
+        for name in ["nginx", "php-apache", "react-nginx"] {
+            assert!(problems[0].contains(name), "{} is not in {}", name, problems[0]);
+        }
+    }
+}
@@ -1,7 +1,25 @@
+mod error;
 mod compose;
+mod compose_model;
+mod answers;
+mod validator;
+mod env_file;
 mod service;
+mod ports;
+mod dependencies;
+mod healthcheck;
+mod resources;
+mod security;
+mod certificates;
+mod profiles;
+mod instances;
 mod select;
 mod catalog;
+mod lint;
+mod validate;
+mod audit;
+mod merge;
+mod output;
 
 
 
@@ -9,24 +27,240 @@ mod generic{
     use serde::{Deserialize, Serialize};
     use std::collections::HashMap;
     use std::fs::{File, OpenOptions};
-    use std::io::{Read, Error, ErrorKind, BufReader, Write};
+    use std::io::{Error, Read, BufReader, Write};
     use serde_json::{Value, json};
     use std::env;
-
+    use crate::error::RyujinError;
+    use crate::dependencies::{missing_requirements, find_cycle};
+    use crate::profiles::{parse_choice, check_profiles};
+    use crate::instances::{parse_instance, check_instance_name};
+    use crate::output::Output;
+
+
+    /// `QuestionKind` is an enum that represents the type of answer expected by a question.
+    ///
+    /// Each kind has its own validator (see the `validator` module):
+    /// * `Port`: A TCP/UDP port number between 1 and 65535.
+    /// * `HostPath`: A path on the host, that may not exist yet.
+    /// * `File`: A path to an existing file on the host.
+    /// * `Dir`: A path to an existing directory on the host.
+    /// * `ContainerName`: A valid Docker container name.
+    /// * `Volume`: Either a host path (starting with `/`, `.` or `~`) mounted as a bind mount, or the name of a named volume.
+    /// * `Enum`: One of the values listed in `choices`.
+    /// * `Bool`: A yes/no answer, stored as `true` or `false`.
+    /// * `Int`: An integer.
+    /// * `Text`: Free text. This is the default kind.
+    #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum QuestionKind {
+        Port,
+        HostPath,
+        File,
+        Dir,
+        ContainerName,
+        Volume,
+        Enum,
+        Bool,
+        Int,
+        #[default]
+        Text,
+    }
 
     /// `Question` is a struct that represents a question in the JSON data.
     ///
     /// It contains the following fields:
     /// * `question`: A `String` that represents the text of the question.
     /// * `variable`: A `String` that represents the variable associated with the question.
+    /// * `kind`: A `QuestionKind` that represents the type of the expected answer. Defaults to `text`.
+    /// * `min`: An `Option<i64>` that represents the lower bound of the answer (value for numbers, length for text).
+    /// * `max`: An `Option<i64>` that represents the upper bound of the answer (value for numbers, length for text).
+    /// * `regex`: An `Option<String>` that represents a regular expression the answer must match.
+    /// * `choices`: A `Vec<String>` that contains the allowed answers of an `enum` question.
+    /// * `default`: An `Option<String>` that represents the default answer. It can be a literal or a Tera expression over the previous answers of the service and `project_name`, e.g. `{{ project_name }}-nginx`.
+    /// * `when`: An `Option<String>` that represents a Tera condition over the previous answers of the service, e.g. `package_manager == "npm"`. The question is skipped when it is false.
+    /// * `driver`: An `Option<String>` that represents the driver of the named volume, for a `volume` question answered with a volume name.
+    /// * `driver_opts`: A `HashMap<String, String>` that contains the driver options of the named volume, for a `volume` question answered with a volume name.
+    /// * `secret`: A `bool` that indicates whether the answer is a secret. Secrets are read without echo and written to the `.env` file instead of the docker-compose.
     /// * `answer`: An `Option<String>` that represents the answer to the question. This field is not present in the JSON data and is used to store the user's answer.
+    /// * `skipped`: A `bool` that indicates whether the question was skipped because of its `when` condition. This field is not present in the JSON data.
     ///
     /// This struct is used to parse the JSON data and store the information about a question.
     #[derive(Serialize, Deserialize, Debug, Clone)]
     pub struct Question {
         pub question: String,
         pub variable: String,
+        #[serde(default)]
+        pub kind: QuestionKind,
+        #[serde(default)]
+        pub min: Option<i64>,
+        #[serde(default)]
+        pub max: Option<i64>,
+        #[serde(default)]
+        pub regex: Option<String>,
+        #[serde(default)]
+        pub choices: Vec<String>,
+        #[serde(default)]
+        pub default: Option<String>,
+        #[serde(default)]
+        pub when: Option<String>,
+        #[serde(default)]
+        pub driver: Option<String>,
+        #[serde(default)]
+        pub driver_opts: HashMap<String, String>,
+        #[serde(default)]
+        pub secret: bool,
         pub answer: Option<String>,
+        #[serde(skip)]
+        pub skipped: bool,
+    }
+
+    /// `PortMapping` is a struct that represents a port published by a service on the host.
+    ///
+    /// It contains the following fields:
+    /// * `name`: A `String` that names the port inside the service, e.g. `http`. Templates read the host port as `{{ ports.<name> }}`.
+    /// * `host`: An `Option<u16>` that represents the host port, when it is fixed by the catalog.
+    /// * `variable`: An `Option<String>` that represents the question whose answer is the host port, when the user chooses it.
+    /// * `container`: A `u16` that represents the port inside the container.
+    ///
+    /// This struct is used to detect the host ports published by several selected services.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct PortMapping {
+        pub name: String,
+        #[serde(default)]
+        pub host: Option<u16>,
+        #[serde(default)]
+        pub variable: Option<String>,
+        pub container: u16,
+    }
+
+    /// `SecretFile` is a struct that represents a file of the host that can be mounted as a Docker secret.
+    ///
+    /// It contains the following fields:
+    /// * `variable`: A `String` that represents the question whose answer is the path of the file on the host.
+    /// * `target`: A `String` that represents the path of the file in the container.
+    ///
+    /// This struct is used to replace the bind mount of a private key by a compose secret with `--docker-secrets`.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SecretFile {
+        pub variable: String,
+        pub target: String,
+    }
+
+    /// `TlsFiles` is a struct that represents the certificate and the private key a service serves TLS with.
+    ///
+    /// It contains the following fields:
+    /// * `certificate`: A `String` that represents the question whose answer is the path of the certificate.
+    /// * `key`: A `String` that represents the question whose answer is the path of the private key.
+    ///
+    /// This struct is used to generate a self-signed certificate and its key when the certificate question is left empty.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct TlsFiles {
+        pub certificate: String,
+        pub key: String,
+    }
+
+    /// `Healthcheck` is a struct that represents the healthcheck of a service.
+    ///
+    /// It contains the following fields:
+    /// * `test`: A `Vec<String>` that represents the command run in the container, e.g. `["CMD-SHELL", "curl -f http://localhost/ || exit 1"]`.
+    /// * `interval`: An `Option<String>` that represents the duration between two checks, e.g. `30s`.
+    /// * `timeout`: An `Option<String>` that represents the duration after which a check is considered failed.
+    /// * `retries`: An `Option<u32>` that represents the number of consecutive failures needed to report the container as unhealthy.
+    /// * `start_period`: An `Option<String>` that represents the time given to the container to start before the failures are counted.
+    ///
+    /// The durations use the compose format, e.g. `1m30s`. Docker uses its own default for the fields that are not set.
+    #[derive(Serialize, Deserialize, Debug, Clone, Default)]
+    pub struct Healthcheck {
+        pub test: Vec<String>,
+        #[serde(default)]
+        pub interval: Option<String>,
+        #[serde(default)]
+        pub timeout: Option<String>,
+        #[serde(default)]
+        pub retries: Option<u32>,
+        #[serde(default)]
+        pub start_period: Option<String>,
+    }
+
+    /// `Size` is an enum that represents the sizing presets of the resource limits, chosen with `--size`.
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Size {
+        Small,
+        #[default]
+        Medium,
+        Large,
+    }
+
+    impl std::fmt::Display for Size {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                Size::Small => write!(f, "small"),
+                Size::Medium => write!(f, "medium"),
+                Size::Large => write!(f, "large"),
+            }
+        }
+    }
+
+    /// `ResourceLimits` is a struct that represents the resources a container can use.
+    ///
+    /// It contains the following fields:
+    /// * `cpus`: An `Option<String>` that represents the number of CPUs, e.g. `0.5`.
+    /// * `memory`: An `Option<String>` that represents the amount of memory, e.g. `512m`.
+    /// * `pids`: An `Option<u32>` that represents the number of processes, which stops fork bombs.
+    #[derive(Serialize, Deserialize, Debug, Clone, Default)]
+    pub struct ResourceLimits {
+        #[serde(default)]
+        pub cpus: Option<String>,
+        #[serde(default)]
+        pub memory: Option<String>,
+        #[serde(default)]
+        pub pids: Option<u32>,
+    }
+
+    /// `SecurityOption` is an enum that represents a hardening option of the security profiles.
+    ///
+    /// * `NoNewPrivileges`: `security_opt: [no-new-privileges:true]`, the processes cannot gain privileges (setuid binaries...).
+    /// * `CapDrop`: `cap_drop: [ALL]`, only the capabilities in `cap_add` are kept.
+    /// * `ReadOnly`: `read_only: true`, the root filesystem is read-only and the `tmpfs` directories are writable.
+    /// * `User`: `user:`, the container runs as the non-root user of the catalog.
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum SecurityOption {
+        NoNewPrivileges,
+        CapDrop,
+        ReadOnly,
+        User,
+    }
+
+    impl std::fmt::Display for SecurityOption {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                SecurityOption::NoNewPrivileges => write!(f, "no_new_privileges"),
+                SecurityOption::CapDrop => write!(f, "cap_drop"),
+                SecurityOption::ReadOnly => write!(f, "read_only"),
+                SecurityOption::User => write!(f, "user"),
+            }
+        }
+    }
+
+    /// `Security` is a struct that represents how the security profiles apply to a service.
+    ///
+    /// It contains the following fields:
+    /// * `cap_add`: A `Vec<String>` that contains the capabilities the service needs once every capability is dropped, e.g. `NET_BIND_SERVICE`.
+    /// * `tmpfs`: A `Vec<String>` that contains the directories the service writes to, mounted as tmpfs when the root filesystem is read-only.
+    /// * `user`: An `Option<String>` that represents the non-root user the service runs as, e.g. `nginx` or `1000:1000`.
+    /// * `opt_out`: A `Vec<SecurityOption>` that contains the hardening options the service cannot support.
+    #[derive(Serialize, Deserialize, Debug, Clone, Default)]
+    pub struct Security {
+        #[serde(default)]
+        pub cap_add: Vec<String>,
+        #[serde(default)]
+        pub tmpfs: Vec<String>,
+        #[serde(default)]
+        pub user: Option<String>,
+        #[serde(default)]
+        pub opt_out: Vec<SecurityOption>,
     }
 
     /// `Service` is a struct that represents a service in the JSON data.
@@ -41,8 +275,19 @@ mod generic{
     /// * `links`: A `HashMap<String, String>` that contains links to the service's documentation, source code, and other resources.
     /// * `tags`: A `Vec<String>` that contains the service's tags.
     /// * `template_path`: A `String` that represents the path to the template for the service.
+    /// * `networks`: A `Vec<String>` that contains the networks the service is attached to, e.g. `frontend`. A service without networks stays on the default network.
+    /// * `ports`: A `Vec<PortMapping>` that contains the ports the service publishes on the host.
+    /// * `secret_files`: A `Vec<SecretFile>` that contains the files that can be mounted as Docker secrets.
+    /// * `tls`: An `Option<TlsFiles>` that represents the certificate and key questions, for the services that serve TLS.
+    /// * `healthcheck`: An `Option<Healthcheck>` that represents how Docker checks that the service is healthy.
+    /// * `requires`: A `Vec<String>` that contains the services this service cannot run without. They are added to the selection.
+    /// * `optional_after`: A `Vec<String>` that contains the services this service starts after, when they are selected too.
+    /// * `resources`: A `HashMap<Size, ResourceLimits>` that contains the resource limits recommended for each size preset.
+    /// * `security`: A `Security` that represents how the security profiles apply to the service.
     /// * `variables`: A `Vec<String>` that contains the variables associated with the service.
     /// * `questions`: A `Vec<Question>` that contains the questions associated with the service.
+    /// * `profiles`: A `Vec<String>` that contains the Compose profiles the service was selected with (`<service>@<profile>`). It is not read from the catalog.
+    /// * `key_group`: An `Option<u32>` that represents the group of the TLS key generated for the service, added to its containers so they can read the key. It is not read from the catalog.
     ///
     /// This struct is used to parse the JSON data and store the information about a service.
     #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -56,8 +301,30 @@ mod generic{
         pub links: HashMap<String, String>,
         pub tags: Vec<String>,
         pub template_path: String,
+        #[serde(default)]
+        pub networks: Vec<String>,
+        #[serde(default)]
+        pub ports: Vec<PortMapping>,
+        #[serde(default)]
+        pub secret_files: Vec<SecretFile>,
+        #[serde(default)]
+        pub tls: Option<TlsFiles>,
+        #[serde(default)]
+        pub healthcheck: Option<Healthcheck>,
+        #[serde(default)]
+        pub requires: Vec<String>,
+        #[serde(default)]
+        pub optional_after: Vec<String>,
+        #[serde(default)]
+        pub resources: HashMap<Size, ResourceLimits>,
+        #[serde(default)]
+        pub security: Security,
         pub variables: Vec<String>,
         pub questions: Vec<Question>,
+        #[serde(skip)]
+        pub profiles: Vec<String>,
+        #[serde(skip)]
+        pub key_group: Option<u32>,
     }
 
     /// `ReadmePartial` is a struct that represents a part of a README file.
@@ -66,6 +333,7 @@ mod generic{
     /// * `service`: A `String` that represents the name of the service.
     /// * `informations_readme`: A `String` that contains the information section of the README.
     /// * `configuration_readme`: A `String` that contains the configuration section of the README.
+    /// * `profiles`: A `Vec<String>` that contains the Compose profiles the service starts with, empty when it always starts.
     ///
     /// This struct is used to generate a README file for a service by combining these parts.
     #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -73,6 +341,7 @@ mod generic{
         pub service: String,
         pub informations_readme: String,
         pub configuration_readme: String,
+        pub profiles: Vec<String>,
     }
 
     /// `Services` is a type alias for a `HashMap` where the key is a `String` representing the name of a service,
@@ -81,28 +350,41 @@ mod generic{
     /// This type is used to store and manipulate a collection of services in the program.
     pub type Services = HashMap<String, Service>;
 
+    /// Returns the path to the ryujin-cli directory.
+    ///
+    /// The path is read from the `RYUJIN_CLI_PATH` environment variable. It is used as the base path to the catalog,
+    /// the templates and the selection configuration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return a `RyujinError::Catalog` if the `RYUJIN_CLI_PATH` environment variable is not set.
+    pub fn ryujin_dir() -> Result<String, RyujinError> {
+        env::var("RYUJIN_CLI_PATH").map_err(|_| RyujinError::Catalog("The RYUJIN_CLI_PATH env variable was not found. Please set it to the path of the ryujin-cli directory".to_string()))
+    }
+
     /// Loads the services data from a JSON file.
     ///
-    /// This function opens the JSON file at the path "../../services/services.json", reads its contents into a string,
+    /// This function opens the JSON file at the path "$RYUJIN_CLI_PATH/services/services.json", reads its contents into a string,
     /// and then deserializes the string into a `Services` object using the `serde_json::from_str` function.
     ///
     /// # Returns
     ///
-    /// A `Services` object representing the services data. If the file cannot be opened, read, or parsed, the function will panic.
+    /// A `Services` object representing the services data.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// This function will panic if the file cannot be opened, read, or parsed into a `Services` object.
-    pub fn load_services() -> Services {
+    /// This function will return a `RyujinError::Catalog` if the file cannot be opened, read, or parsed into a `Services` object.
+    pub fn load_services() -> Result<Services, RyujinError> {
         // get the path to the ryujin-cli directory
         // it is used as the base path to the services.json file
-        let dir_path = env::var("RYUJIN_CLI_PATH").expect("The RYUJIN_CLI_PATH env variable was not found. Please set it to the path of the ryujin-cli directory");
-        
-        let mut file = File::open(&format!("{}/services/services.json", dir_path)).expect(&format!("Unable to open file : {}/services/services.json", dir_path));
+        let dir_path = ryujin_dir()?;
+        let catalog_path = format!("{}/services/services.json", dir_path);
+
+        let mut file = File::open(&catalog_path).map_err(|e| RyujinError::Catalog(format!("Unable to open file {}: {}", catalog_path, e)))?;
         let mut contents = String::new();
-        file.read_to_string(&mut contents).expect(&format!("Unable to read file : {}/services/services.json", dir_path));
-        let services: Services = serde_json::from_str(&contents).expect(&format!("Unable to parse JSON : {}/services/services.json", dir_path));
-        services
+        file.read_to_string(&mut contents).map_err(|e| RyujinError::Catalog(format!("Unable to read file {}: {}", catalog_path, e)))?;
+        let services: Services = serde_json::from_str(&contents).map_err(|e| RyujinError::Catalog(format!("Unable to parse JSON {}: {}", catalog_path, e)))?;
+        Ok(services)
     }
 
     /// Loads the user's selection from a JSON file.
@@ -115,8 +397,8 @@ mod generic{
     ///
     /// # Errors
     ///
-    /// This function will return an error if the `conf/conf.json` file cannot be opened, if the file's
-    /// contents cannot be parsed as JSON, or if the `selected_services` field is not an array.
+    /// This function will return a `RyujinError::Selection` if the `conf/conf.json` file cannot be opened, if the file's
+    /// contents cannot be parsed as JSON, or if the `selected_services` field is not an array of strings.
     ///
     /// # Examples
     ///
@@ -127,19 +409,23 @@ mod generic{
     ///     None => println!("No services selected"),
     /// }
     /// ```
-    pub fn load_selection_from_json() -> Result<Option<Vec<String>>, Box<dyn std::error::Error>> {
+    pub fn load_selection_from_json() -> Result<Option<Vec<String>>, RyujinError> {
         // get the path to the ryujin-cli directory
         // it is used as the base path to the conf.json file
-        let dir_path = env::var("RYUJIN_CLI_PATH").expect("The RYUJIN_CLI_PATH env variable was not found. Please set it to the path of the ryujin-cli directory");
+        let dir_path = ryujin_dir()?;
         
-        let file = File::open(format!("{dir_path}/conf/conf.json"))?;
+        let file = File::open(format!("{dir_path}/conf/conf.json"))
+            .map_err(|e| RyujinError::Selection(format!("Unable to open {dir_path}/conf/conf.json: {e}")))?;
         let reader = BufReader::new(file);
-        let json: Value = serde_json::from_reader(reader)?;
+        let json: Value = serde_json::from_reader(reader)
+            .map_err(|e| RyujinError::Selection(format!("Unable to parse JSON {dir_path}/conf/conf.json: {e}")))?;
 
         let selected_services = json["selected_services"].as_array()
-        .ok_or_else(|| Error::new(std::io::ErrorKind::InvalidData, "selected_services is not an array."))?;
+        .ok_or_else(|| RyujinError::Selection("selected_services is not an array.".to_string()))?;
 
-        let services : Vec<String> = selected_services.iter().map(|s| s.as_str().expect("Not a string").to_owned()).collect();
+        let services : Vec<String> = selected_services.iter()
+            .map(|s| s.as_str().map(|s| s.to_owned()).ok_or_else(|| RyujinError::Selection(format!("{} in selected_services is not a string.", s))))
+            .collect::<Result<Vec<String>, RyujinError>>()?;
         
         if services.is_empty(){
             Ok(None)
@@ -161,8 +447,8 @@ mod generic{
     ///
     /// # Errors
     ///
-    /// This function will return an error if the `conf/conf.json` file cannot be opened, if the file's
-    /// contents cannot be parsed as JSON, or if the file cannot be written to.
+    /// This function will return a `RyujinError::Selection` if the `conf/conf.json` file cannot be opened or if the file's
+    /// contents cannot be parsed as JSON, and a `RyujinError::Io` if the file cannot be written to.
     ///
     /// # Examples
     ///
@@ -173,14 +459,16 @@ mod generic{
     ///     Err(e) => println!("Error saving selection: {}", e),
     /// }
     /// ```
-    pub fn save_selection_to_json(user_selection: &Vec<String>) -> Result<(), Box<dyn std::error::Error>>{
+    pub fn save_selection_to_json(user_selection: &Vec<String>) -> Result<(), RyujinError>{
         // get the path to the ryujin-cli directory
         // it is used as the base path to the templates
-        let dir_path = env::var("RYUJIN_CLI_PATH").expect("The RYUJIN_CLI_PATH env variable was not found. Please set it to the path of the ryujin-cli directory");
+        let dir_path = ryujin_dir()?;
         
-        let file = File::open(format!("{dir_path}/conf/conf.json"))?;
+        let file = File::open(format!("{dir_path}/conf/conf.json"))
+            .map_err(|e| RyujinError::Selection(format!("Unable to open {dir_path}/conf/conf.json: {e}")))?;
         let reader = BufReader::new(file);
-        let mut json: Value = serde_json::from_reader(reader)?;
+        let mut json: Value = serde_json::from_reader(reader)
+            .map_err(|e| RyujinError::Selection(format!("Unable to parse JSON {dir_path}/conf/conf.json: {e}")))?;
 
         let selection_json = json!(user_selection);
 
@@ -192,7 +480,7 @@ mod generic{
         let mut file = std::io::BufWriter::new(file);
 
         
-        write!(file, "{}", json.to_string())?;
+        write!(file, "{}", json)?;
 
         Ok(())
     }
@@ -202,46 +490,110 @@ mod generic{
     /// This function takes a reference to a `Services` object and a vector of user choices. It iterates over the user choices,
     /// and for each choice, it tries to find a corresponding service in the `Services` object. If a service is found, it is
     /// added to a new `Services` object that will be returned. If a service is not found, the choice is added to a list of
-    /// not found services.
+    /// not found services. A choice can put the service in Compose profiles, e.g. `portainer@admin` (see `parse_choice`).
+    ///
+    /// A choice can also name an instance of the service, e.g. `nginx:site-a` (see `parse_instance`), so that the same
+    /// service is selected several times. The instance is then keyed by its name instead of the name of the service:
+    /// it gets its own answers, its own compose service and its own readme section.
+    ///
+    /// The services required by the selection (see `Service.requires`) are then added, once the user agreed to it.
+    /// When the user cannot be asked, the missing services are reported instead. Finally, the selection is checked for
+    /// dependency cycles, and for services that require a service they cannot start with because of their profiles.
     ///
     /// # Arguments
     ///
     /// * `services` - A reference to a `Services` object that contains all available services.
     /// * `user_choice` - A vector of `String` that represents the user's choices.
+    /// * `interactive` - A `bool` that indicates whether the user can be asked to add the required services.
+    /// * `output` - A reference to the `Output`, which prints the questions on the standard error with `--stdout`.
+    /// * `get_input` - A function that returns a `Result<String, Error>`. This function is called to get the user's input.
     ///
     /// # Returns
     ///
-    /// * `Ok(Services)` - A `Services` object that contains only the services selected by the user.
-    /// * `Err(Error)` - An `Error` object with `ErrorKind::InvalidInput` and a message that lists the services that were not found.
+    /// * `Ok(Services)` - A `Services` object that contains the services selected by the user and the services they require.
+    /// * `Err(RyujinError)` - A `RyujinError::Input` with a message that lists the services that were not found.
     ///
     /// # Errors
     ///
-    /// This function will return an error if one or more of the user's choices do not correspond to a service in the `Services` object.
+    /// This function will return a `RyujinError::Input` if one or more of the user's choices do not correspond to a service in
+    /// the `Services` object, if a profile or an instance name is invalid or already taken, even once normalized for the
+    /// `.env` variables (`site-a` and `site_a`), if a required service is not
+    /// added or cannot start, and a `RyujinError::Catalog` if a required service is
+    /// not in the catalog or if the selected services depend on each other in a cycle.
     ///
     /// # Example
     ///
     /// ```
     /// let services = load_services();
     /// let user_choice = vec!["service1".to_string(), "service2".to_string()];
-    /// let selected_services = get_selected_services(&services, user_choice).unwrap();
+    /// let selected_services = get_selected_services(&services, user_choice, true, &Output::default(), get_input).unwrap();
     /// ```
-    pub fn get_selected_services(services: &Services, user_choice: Vec<String>) -> Result<Services, Error> {
+    pub fn get_selected_services<F: FnMut() -> Result<String, Error>>(services: &Services, user_choice: Vec<String>, interactive: bool, output: &Output, mut get_input: F) -> Result<Services, RyujinError> {
         let mut selected_services = Services::new();
         let mut not_found: Vec<String> = Vec::new();
 
         for choice in user_choice {
-            match services.get(&choice) {
-                Some(service) => { selected_services.insert(choice, service.clone()); },
-                None => { not_found.push(choice); },
+            let (name, profiles) = parse_choice(&choice)?;
+            let (name, instance) = parse_instance(&name)?;
+            match services.get(&name) {
+                Some(service) => {
+                    let key = instance.unwrap_or_else(|| name.clone());
+                    // An instance named after a catalog service would be taken for it by the dependencies
+                    if key != name && services.contains_key(&key) {
+                        return Err(RyujinError::Input(format!("The instance {}:{} cannot be named after the service {} of the catalog.", name, key, key)));
+                    }
+                    if selected_services.get(&key).is_some_and(|selected| selected.name != name) {
+                        return Err(RyujinError::Input(format!("The instance name {} is used by several services.", key)));
+                    }
+                    check_instance_name(&selected_services, &key)?;
+                    // A service chosen twice belongs to the profiles of both choices
+                    let selected = selected_services.entry(key).or_insert_with(|| service.clone());
+                    for profile in profiles {
+                        if !selected.profiles.contains(&profile) {
+                            selected.profiles.push(profile);
+                        }
+                    }
+                },
+                None => { not_found.push(name); },
             }
         }
 
         if !not_found.is_empty() {
             let error_message = format!("The following services were not found: {:?}. Use the catalog command to get a list of available services.", not_found);
-            Err(Error::new(ErrorKind::InvalidInput, error_message))
-        } else {
-            Ok(selected_services)
+            return Err(RyujinError::Input(error_message));
+        }
+
+        // Add the required services, until the requirements of the added services are met too
+        loop {
+            let missing = missing_requirements(&selected_services);
+            if missing.is_empty() {
+                break;
+            }
+            if !interactive {
+                let report: Vec<String> = missing.iter().map(|(service, required)| format!("{} requires {}", service, required)).collect();
+                return Err(RyujinError::Input(format!("Some required services are not selected:\n{}\nAdd them with --services.", report.join("\n"))));
+            }
+            for (service, required) in missing {
+                if selected_services.contains_key(&required) {
+                    continue;
+                }
+                let required_service = services.get(&required)
+                    .ok_or_else(|| RyujinError::Catalog(format!("The service {} requires {}, which is not in the catalog.", service, required)))?;
+                output.notice(&format!("The service {} requires {}, which is not selected. Add {} to the docker-compose? [Y/n]", service, required, required));
+                let input = get_input()?;
+                match input.trim().to_lowercase().as_str() {
+                    "" | "y" | "yes" => { selected_services.insert(required.clone(), required_service.clone()); },
+                    _ => return Err(RyujinError::Input(format!("The service {} cannot be generated without {}.", service, required))),
+                }
+            }
+        }
+
+        if let Some(cycle) = find_cycle(&selected_services) {
+            return Err(RyujinError::Catalog(format!("The selected services depend on each other in a cycle: {}.", cycle.join(" -> "))));
         }
+        check_profiles(&selected_services)?;
+
+        Ok(selected_services)
     }
 
 
@@ -250,28 +602,30 @@ mod generic{
 
 
 
-use clap::{command, Arg, Command};
+use clap::{command, Arg, ArgAction, ArgMatches, Command};
 
 
-use crate::generic::{load_services, load_selection_from_json, save_selection_to_json, Services};
+use crate::generic::{load_services, load_selection_from_json, save_selection_to_json, Services, Size};
+use crate::error::RyujinError;
 /// Entry point of the Ryujin-CLI application.
 ///
 /// This function is responsible for parsing command line arguments and executing the corresponding actions.
 /// It supports several subcommands:
-/// - `compose`: Starts the process of creating a docker-compose file. It takes optional `services` argument and a required `output-dir` argument.
+/// - `compose`: Starts the process of creating a docker-compose file. It takes optional `services`, `answers`, `port`, `internal-networks`, `docker-secrets`, `healthcheck`, `no-healthcheck`, `size`, `limit`, `security`, `merge`, `env-file`, `self-signed`, `tls-cn`, `tls-san`, `tls-ca`, `dry-run` and `stdout` arguments and a required `output-dir` argument.
 /// - `catalog`: Displays the list of services to add to the docker-compose. It takes optional `long`, `tags`, and `name` arguments.
+///   Its `lint` subcommand checks the consistency of the catalog.
 /// - `service`: Displays detailed information about a specific service. It takes a required `service_name` argument.
 /// - `select`: Allows you to save a service selection and update it before using the compose command. It takes optional `new`, `add`, `delete`, `remove`, `print`, and `services` arguments.
+/// - `validate`: Validates a docker-compose.yml against the Compose specification. It takes a required `dir` argument.
+/// - `audit`: Reports the risky settings of a docker-compose.yml. It takes a required `dir` argument and optional `format` and `fail-on` arguments.
 ///
 /// Each subcommand has its own set of arguments and behaviors.
 ///
 /// # Errors
-/// This function will exit the process with a non-zero status code if an error occurs.
-
+/// This function will exit the process with the exit code of the `RyujinError` if an error occurs (see the `error` module).
 fn main(){
-    let mut services: Services = load_services();
-
     let matches = command!()
+        .after_help("Exit codes: 0 success, 2 invalid input, 3 catalog error, 4 selection error, 5 template error, 6 I/O error, 7 problems found in the checked file (validate, audit --fail-on).")
         .subcommand(Command::new("compose")
             .about("Start the process of creating a docker-compose.")
             .arg(Arg::new("services")
@@ -279,7 +633,7 @@ fn main(){
                 .long("services")
                 .required(false) // TEST
                 .value_delimiter(',')
-                .help(" List of services to add to the docker-compose.")
+                .help(" List of services to add to the docker-compose. Add :<instance> to select a service several times, e.g. nginx:site-a,nginx:site-b, and @<profile> to start it only with that Compose profile, e.g. portainer@admin.")
             )
             .arg(Arg::new("output-dir")
                 .short('o')
@@ -287,9 +641,121 @@ fn main(){
                 .required(true)
                 .help("Path of the output directory where the docker-compose should be created.")
             )
+            .arg(Arg::new("answers")
+                .long("answers")
+                .required(false)
+                .help("Path of a JSON, YAML or TOML file that answers the questions of the services.")
+            )
+            .arg(Arg::new("port")
+                .long("port")
+                .required(false)
+                .action(ArgAction::Append)
+                .value_name("service.port=host")
+                .help("Publish a port of a service on another host port, e.g. nginx.http=8081. Can be repeated.")
+            )
+            .arg(Arg::new("internal-networks")
+                .long("internal-networks")
+                .required(false)
+                .value_delimiter(',')
+                .help("List of networks cut from the outside world (internal: true), e.g. backend,admin.")
+            )
+            .arg(Arg::new("docker-secrets")
+                .long("docker-secrets")
+                .required(false)
+                .num_args(0)
+                .help("Mount the private keys as Docker secrets instead of bind mounts.")
+            )
+            .arg(Arg::new("healthcheck")
+                .long("healthcheck")
+                .required(false)
+                .action(ArgAction::Append)
+                .value_name("service.setting=value")
+                .help("Tune the healthcheck of a service (test, interval, timeout, retries or start_period), e.g. nginx.interval=10s. Can be repeated.")
+            )
+            .arg(Arg::new("no-healthcheck")
+                .long("no-healthcheck")
+                .required(false)
+                .num_args(0..)
+                .value_delimiter(',')
+                .value_name("services")
+                .help("Do not write the healthchecks of the services listed, or of every service when none is listed.")
+            )
+            .arg(Arg::new("size")
+                .long("size")
+                .required(false)
+                .value_parser(["small", "medium", "large"])
+                .default_value("medium")
+                .help("Size preset of the CPU, memory and process limits recommended by the catalog.")
+            )
+            .arg(Arg::new("limit")
+                .long("limit")
+                .required(false)
+                .action(ArgAction::Append)
+                .value_name("service.limit=value")
+                .help("Override a resource limit of a service (cpus, memory or pids), e.g. pwn.memory=2g. Can be repeated.")
+            )
+            .arg(Arg::new("security")
+                .long("security")
+                .required(false)
+                .value_parser(["none", "baseline", "strict"])
+                .default_value("baseline")
+                .help("Security profile of the services: baseline drops the capabilities and privileges, strict also makes the root filesystem read-only and runs as a non-root user.")
+            )
+            .arg(Arg::new("merge")
+                .long("merge")
+                .required(false)
+                .num_args(0)
+                .help("Merge the services into the existing docker-compose.yml of the output directory instead of asking to erase it. The services and sections added by hand are kept.")
+            )
+            .arg(Arg::new("env-file")
+                .long("env-file")
+                .required(false)
+                .num_args(0)
+                .help("Write every answer to the .env file and refer to it with ${VAR} interpolation in the docker-compose.")
+            )
+            .arg(Arg::new("self-signed")
+                .long("self-signed")
+                .required(false)
+                .num_args(0)
+                .help("Generate a self-signed certificate and key for the certificate questions left empty, without asking.")
+            )
+            .arg(Arg::new("tls-cn")
+                .long("tls-cn")
+                .required(false)
+                .default_value("localhost")
+                .help("Common name of the generated certificates.")
+            )
+            .arg(Arg::new("tls-san")
+                .long("tls-san")
+                .required(false)
+                .value_delimiter(',')
+                .help("Subject alternative names of the generated certificates, DNS names or IP addresses, e.g. localhost,127.0.0.1. Defaults to the common name.")
+            )
+            .arg(Arg::new("tls-ca")
+                .long("tls-ca")
+                .required(false)
+                .num_args(0)
+                .help("Sign the generated certificates with a local development CA, created in the certs directory of the output directory if needed.")
+            )
+            .arg(Arg::new("dry-run")
+                .long("dry-run")
+                .required(false)
+                .num_args(0)
+                .help("Render the files without writing them, and print the list of the files that would be written.")
+            )
+            .arg(Arg::new("stdout")
+                .long("stdout")
+                .required(false)
+                .num_args(0)
+                .help("Print the docker-compose.yml on the standard output instead of writing it. Nothing is written, the other messages go to the standard error.")
+            )
         )
         .subcommand(Command::new("catalog")
             .about("Displays the list of services you can add to your docker-compose.")
+            .args_conflicts_with_subcommands(true)
+            .subcommand(Command::new("lint")
+                .about("Checks the consistency of services.json and of the templates of the catalog.")
+            )
             .arg(Arg::new("long")
                 .short('l')
                 .long("long")
@@ -367,9 +833,73 @@ fn main(){
                 .help("The service you want")
             )
         )
+        .subcommand(Command::new("validate")
+            .about("Validates a docker-compose.yml against the Compose specification.")
+            .arg(Arg::new("dir")
+                .required(true)
+                .help("Directory that contains the docker-compose.yml, or path of the file.")
+            )
+        )
+        .subcommand(Command::new("audit")
+            .about("Reports the risky settings of a docker-compose.yml, with their severity and how to fix them.")
+            .arg(Arg::new("dir")
+                .required(true)
+                .help("Directory that contains the docker-compose.yml, or path of the file.")
+            )
+            .arg(Arg::new("format")
+                .long("format")
+                .required(false)
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .help("Format of the report.")
+            )
+            .arg(Arg::new("fail-on")
+                .long("fail-on")
+                .required(false)
+                .value_parser(["low", "medium", "high", "critical", "never"])
+                .default_value("high")
+                .help("Severity from which a finding makes the command fail.")
+            )
+        )
           
         .get_matches();
 
+    if let Err(e) = run(&matches) {
+        eprintln!("{}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Runs the subcommand selected on the command line.
+///
+/// # Arguments
+///
+/// * `matches` - A reference to the `ArgMatches` parsed by clap.
+///
+/// # Errors
+///
+/// This function will return the `RyujinError` of the subcommand that failed.
+fn run(matches: &ArgMatches) -> Result<(), RyujinError> {
+    // The validate command does not need the catalog
+    if let Some(("validate", validate_matches)) = matches.subcommand() {
+        let dir = validate_matches.get_one::<String>("dir").expect("dir is a required argument");
+        return validate::handle(dir);
+    }
+    // Neither does the audit command
+    if let Some(("audit", audit_matches)) = matches.subcommand() {
+        let dir = audit_matches.get_one::<String>("dir").expect("dir is a required argument");
+        let json = audit_matches.get_one::<String>("format").map(String::as_str) == Some("json");
+        let fail_on = match audit_matches.get_one::<String>("fail-on").map(String::as_str) {
+            Some("low") => Some(audit::Severity::Low),
+            Some("medium") => Some(audit::Severity::Medium),
+            Some("critical") => Some(audit::Severity::Critical),
+            Some("never") => None,
+            _ => Some(audit::Severity::High),
+        };
+        return audit::handle(dir, json, fail_on);
+    }
+
+    let mut services: Services = load_services()?;
 
     match matches.subcommand() {
         Some(("compose", compose_matches)) => {
@@ -379,33 +909,80 @@ fn main(){
                     choosen_services.push(service.to_string());
                 }
             } else {
-                match load_selection_from_json() {
-                    Ok(Some(json_services)) => {
+                match load_selection_from_json()? {
+                    Some(json_services) => {
                         for services in json_services.iter(){
                             choosen_services.push(services.to_string());
                         }
                     }
-                    Ok(None) => {
-                        println!("The current selection is empty. Please add services to the selection or use the --services option.");
-                        std::process::exit(1)  
+                    None => {
+                        return Err(RyujinError::Input("The current selection is empty. Please add services to the selection or use the --services option.".to_string()));
                     }
-                    Err(err) => {
-                        println!("Loading JSON error: {}", err);
-                        std::process::exit(1)
-                    }
-                    
                 };
             }
             let mut output_dir = String::new();
             if let Some(dir) = compose_matches.get_one::<String>("output-dir"){
                 output_dir = dir.to_string();
             }
-            match compose::handle(&services, choosen_services, &output_dir) {
-                Ok(_) => println!("Docker compose file generated successfully"),
-                Err(e) => eprintln!("Error generating docker compose file: {}", e),
-            }        
+            let mut options = compose::ComposeOptions {
+                answers_path: compose_matches.get_one::<String>("answers").cloned(),
+                internal_networks: compose_matches.get_many::<String>("internal-networks")
+                    .map(|networks| networks.cloned().collect())
+                    .unwrap_or_default(),
+                docker_secrets: compose_matches.get_flag("docker-secrets"),
+                ..Default::default()
+            };
+            if let Some(remaps) = compose_matches.get_many::<String>("port") {
+                for remap in remaps {
+                    options.port_remaps.push(ports::parse_port_remap(remap)?);
+                }
+            }
+            if let Some(tunings) = compose_matches.get_many::<String>("healthcheck") {
+                for tuning in tunings {
+                    options.healthcheck_tunings.push(healthcheck::parse_healthcheck_tuning(tuning)?);
+                }
+            }
+            options.size = match compose_matches.get_one::<String>("size").map(String::as_str) {
+                Some("small") => Size::Small,
+                Some("large") => Size::Large,
+                _ => Size::Medium,
+            };
+            options.security = match compose_matches.get_one::<String>("security").map(String::as_str) {
+                Some("none") => security::SecurityLevel::None,
+                Some("strict") => security::SecurityLevel::Strict,
+                _ => security::SecurityLevel::Baseline,
+            };
+            if let Some(limits) = compose_matches.get_many::<String>("limit") {
+                for limit in limits {
+                    options.limit_overrides.push(resources::parse_limit_override(limit)?);
+                }
+            }
+            if compose_matches.contains_id("no-healthcheck") {
+                options.no_healthcheck = Some(compose_matches.get_many::<String>("no-healthcheck")
+                    .map(|services| services.cloned().collect())
+                    .unwrap_or_default());
+            }
+            options.merge = compose_matches.get_flag("merge");
+            options.env_file = compose_matches.get_flag("env-file");
+            options.dry_run = compose_matches.get_flag("dry-run");
+            options.stdout = compose_matches.get_flag("stdout");
+            options.certificates = certificates::CertificateOptions {
+                self_signed: compose_matches.get_flag("self-signed"),
+                common_name: compose_matches.get_one::<String>("tls-cn").cloned().unwrap_or_else(|| "localhost".to_string()),
+                sans: compose_matches.get_many::<String>("tls-san")
+                    .map(|sans| sans.map(|san| san.trim().to_string()).filter(|san| !san.is_empty()).collect())
+                    .unwrap_or_default(),
+                with_ca: compose_matches.get_flag("tls-ca"),
+            };
+            compose::handle(&services, choosen_services, &output_dir, &options)?;
+            if !options.dry_run && !options.stdout {
+                println!("Docker compose file generated successfully");
+            }
         }
         Some(("catalog", catalog_matches)) => {
+            if let Some(("lint", _)) = catalog_matches.subcommand() {
+                return lint::handle(&services);
+            }
             let mut detailed = false;
             if let Some(val) = catalog_matches.get_one::<bool>("long"){
                 detailed = *val;
@@ -419,49 +996,25 @@ fn main(){
             if let Some(name_str) = catalog_matches.get_one::<String>("name"){
                 name = Some(name_str.to_string());
             }
-            catalog::handle(&mut services, detailed, tags, name).unwrap();
+            catalog::handle(&mut services, detailed, tags, name)?;
         }
         Some(("service", service_matches)) => {
-            let service_name = service_matches.get_one::<String>("service_name").unwrap();
+            let service_name = service_matches.get_one::<String>("service_name").expect("service_name is a required argument");
             match services.get(service_name) {
                 Some(service) => service::print_service_page(service),
-                None => eprintln!("Service not found"),
+                None => return Err(RyujinError::Input(format!("Service {} not found. Use the catalog command to get a list of available services.", service_name))),
             }
         }
         Some(("select", select_matches)) => {
-            let mut user_selection = match load_selection_from_json() {
-                Ok(Some(user_selection)) => user_selection,
-                Ok(None) => Vec::new(),
-                Err(err) => {
-                    println!("Loading JSON error: {}", err);
-                    std::process::exit(1)
-                }
-            
-                
-            };
+            let mut user_selection = load_selection_from_json()?.unwrap_or_default();
 
-            match select::handle(&mut user_selection, select_matches, &services) {
-                Ok(_) => {
-                    match save_selection_to_json(&user_selection){
-                        Ok(_) => {
-                            println!("\n");
-                    }
-                        Err(e) => {
-                            eprintln!("Selection saving error : {}",e);
-                            std::process::exit(1)
-                        }
-                    }
-                }
-                Err(e) => eprintln!("Error updating selection: {}", e),
-            }
-            
-            
-            
+            select::handle(&mut user_selection, select_matches, &services)?;
+            save_selection_to_json(&user_selection)?;
+            println!("\n");
         }
 
         _ => println!("No known subcommand was used"),
     }
 
-   
+    Ok(())
 }
-
@@ -0,0 +1,373 @@
+use crate::compose_model::ComposeFile;
+use crate::error::RyujinError;
+use crate::output::Output;
+use serde_yaml::{Mapping, Value};
+use std::io::Error;
+
+
+/// The top-level sections of the docker-compose where the generated entries are merged, in this order.
+const SECTIONS: [&str; 4] = ["services", "volumes", "networks", "secrets"];
+
+/// `MergeReport` is a struct that describes how the generated entries were merged into an existing docker-compose.
+///
+/// It contains the following fields:
+/// * `added`: A `Vec<String>` that contains the entries that were not in the file, e.g. `services.nginx`.
+/// * `replaced`: A `Vec<String>` that contains the services of the file that were replaced by the generated ones.
+/// * `conflicts`: A `Vec<String>` that describes the keys of the file that differ from the generated ones.
+#[derive(Debug, Default, Clone)]
+pub struct MergeReport {
+    pub added: Vec<String>,
+    pub replaced: Vec<String>,
+    pub conflicts: Vec<String>,
+}
+
+/// `Block` is a struct that represents a key of the YAML text and the lines of its value.
+///
+/// `start` is the line of the key and `end` the line after its last line of content, so the comments and the blank
+/// lines that follow a block stay with the next one.
+#[derive(Debug, Clone)]
+struct Block {
+    key: String,
+    start: usize,
+    end: usize,
+}
+
+/// Returns the indentation of a line.
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Tells whether a line holds YAML content, i.e. is neither blank nor a comment.
+fn is_content(line: &str) -> bool {
+    let trimmed = line.trim();
+    !trimmed.is_empty() && !trimmed.starts_with('#')
+}
+
+/// Returns the key of a `key: value` line and the text that follows the colon, or `None` for another line.
+fn split_key(line: &str) -> Option<(String, String)> {
+    let trimmed = line.trim();
+    if trimmed.starts_with('-') || trimmed == "---" {
+        return None;
+    }
+    let (key, rest) = match trimmed.chars().next() {
+        Some(quote @ ('"' | '\'')) => {
+            let end = trimmed[1..].find(quote)? + 1;
+            (&trimmed[1..end], trimmed[end + 1..].strip_prefix(':')?)
+        },
+        _ => {
+            let colon = trimmed.find(": ").or_else(|| trimmed.strip_suffix(':').map(|key| key.len()))?;
+            (&trimmed[..colon], &trimmed[colon + 1..])
+        },
+    };
+    Some((key.to_string(), rest.trim().to_string()))
+}
+
+/// Lists the keys found at an indentation between two lines, with their blocks.
+fn blocks(lines: &[String], from: usize, to: usize, indent: usize) -> Vec<Block> {
+    let mut found: Vec<Block> = Vec::new();
+    for (index, line) in lines.iter().enumerate().take(to).skip(from) {
+        if !is_content(line) {
+            continue;
+        }
+        if indent_of(line) == indent {
+            if let Some((key, _)) = split_key(line) {
+                found.push(Block { key, start: index, end: index + 1 });
+                continue;
+            }
+        }
+        if let Some(block) = found.last_mut() {
+            block.end = index + 1;
+        }
+    }
+    found
+}
+
+/// Serializes an entry of a section as YAML lines, indented like the entries of the section.
+///
+/// serde_yaml indents with two spaces, the nested lines are indented by `indent` spaces per level instead, so the
+/// entry looks like the rest of the file.
+fn entry_lines(key: &Value, value: &Value, indent: usize) -> Result<Vec<String>, RyujinError> {
+    let mut entry = Mapping::new();
+    entry.insert(key.clone(), value.clone());
+    let yaml = serde_yaml::to_string(&entry)
+        .map_err(|e| RyujinError::Template(format!("Unable to serialize the docker-compose: {}", e)))?;
+    Ok(yaml.lines()
+        .map(|line| format!("{}{}", " ".repeat(indent + indent_of(line) / 2 * indent), line.trim_start()))
+        .collect())
+}
+
+/// Lists the keys of a service whose values differ between the file and the generated service.
+fn differing_keys(existing: &Value, generated: &Value) -> Vec<String> {
+    let empty = Mapping::new();
+    let existing = existing.as_mapping().unwrap_or(&empty);
+    let generated = generated.as_mapping().unwrap_or(&empty);
+
+    let mut keys: Vec<String> = existing.keys().chain(generated.keys())
+        .filter(|key| existing.get(*key) != generated.get(*key))
+        .filter_map(|key| key.as_str().map(str::to_string))
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// Merges one generated entry into the lines of the file.
+fn merge_entry(lines: &mut Vec<String>, section: &str, key: &Value, value: &Value, existing: Option<&Value>, report: &mut MergeReport) -> Result<(), RyujinError> {
+    let name = key.as_str().unwrap_or_default().to_string();
+    let path = format!("{}.{}", section, name);
+
+    match existing {
+        Some(existing) if existing == value => return Ok(()),
+        // A top-level entry of the file may be used by the services of the user, it is kept as is
+        Some(_) if section != "services" => {
+            report.conflicts.push(format!("{}: the definition of the file is kept", path));
+            return Ok(());
+        },
+        Some(existing) => {
+            for differing in differing_keys(existing, value) {
+                report.conflicts.push(format!("{}.{}: replaced by the generated value", path, differing));
+            }
+            report.replaced.push(path.clone());
+        },
+        None => report.added.push(path.clone()),
+    }
+
+    let top = blocks(lines, 0, lines.len(), 0);
+    let Some(section_block) = top.iter().find(|block| block.key == section) else {
+        // The section is added at the end of the file
+        if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+            lines.push(String::new());
+        }
+        lines.push(format!("{}:", section));
+        lines.extend(entry_lines(key, value, 2)?);
+        return Ok(());
+    };
+
+    let (_, header_value) = split_key(&lines[section_block.start]).unwrap_or_default();
+    let header_value = header_value.split(" #").next().unwrap_or_default().trim().to_string();
+    match header_value.as_str() {
+        "" => {},
+        "{}" => lines[section_block.start] = format!("{}:", section),
+        _ => return Err(RyujinError::Input(format!("The {} section of the docker-compose.yml is written on one line and cannot be merged. Write it as a block, one entry per line.", section))),
+    }
+
+    let first_child = (section_block.start + 1..section_block.end).find(|index| is_content(&lines[*index]));
+    let indent = first_child.map(|index| indent_of(&lines[index])).unwrap_or(2);
+    let children = blocks(lines, section_block.start + 1, section_block.end, indent);
+    let new_lines = entry_lines(key, value, indent)?;
+
+    match children.iter().find(|child| child.key == name) {
+        Some(child) => { lines.splice(child.start..child.end, new_lines); },
+        None => {
+            let at = children.last().map(|child| child.end).unwrap_or(section_block.start + 1);
+            lines.splice(at..at, new_lines);
+        },
+    }
+    Ok(())
+}
+
+/// Merges a generated docker-compose into the content of an existing one.
+///
+/// The generated services are added to the `services` section, or replace the services of the file with the same name.
+/// The generated volumes, networks and secrets are added when the file does not declare them yet; the declarations of
+/// the file are kept otherwise, since the services of the user may rely on them. Everything else is kept as written,
+/// comments included, except in the services that are replaced.
+///
+/// # Arguments
+///
+/// * `existing` - The content of the existing `docker-compose.yml`.
+/// * `generated` - A reference to the generated `ComposeFile`.
+///
+/// # Returns
+///
+/// * `Ok((String, MergeReport))` - The merged content and the description of the changes.
+/// * `Err(RyujinError)` - If the existing file cannot be merged.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if the existing file is not a YAML map, or if one of its sections
+/// is written on one line (flow style), and a `RyujinError::Template` if the merged content does not hold the generated entries.
+///
+/// # Example
+///
+/// ```
+/// let (merged, report) = merge_compose(&read_to_string("./output/docker-compose.yml")?, &compose)?;
+/// ```
+pub fn merge_compose(existing: &str, generated: &ComposeFile) -> Result<(String, MergeReport), RyujinError> {
+    let existing_document: Value = serde_yaml::from_str(existing)
+        .map_err(|e| RyujinError::Input(format!("The existing docker-compose.yml cannot be merged, it is not valid YAML: {}", e)))?;
+    if !existing_document.is_mapping() && !existing_document.is_null() {
+        return Err(RyujinError::Input("The existing docker-compose.yml cannot be merged, it is not a YAML map.".to_string()));
+    }
+    let generated_document = serde_yaml::to_value(generated)
+        .map_err(|e| RyujinError::Template(format!("Unable to serialize the docker-compose: {}", e)))?;
+
+    let mut lines: Vec<String> = existing.lines().map(str::to_string).collect();
+    let mut report = MergeReport::default();
+
+    for section in SECTIONS {
+        let Some(entries) = generated_document.get(section).and_then(Value::as_mapping) else { continue };
+        for (key, value) in entries {
+            let existing_entry = existing_document.get(section).and_then(|entries| entries.get(key));
+            merge_entry(&mut lines, section, key, value, existing_entry, &mut report)?;
+        }
+    }
+
+    let mut merged = lines.join("\n");
+    merged.push('\n');
+
+    // The text is edited line by line, check that the result holds what was generated
+    let merged_document: Value = serde_yaml::from_str(&merged)
+        .map_err(|e| RyujinError::Template(format!("Unable to merge the docker-compose: {}", e)))?;
+    for section in SECTIONS {
+        let Some(entries) = generated_document.get(section).and_then(Value::as_mapping) else { continue };
+        for (key, value) in entries {
+            let merged_entry = merged_document.get(section).and_then(|entries| entries.get(key));
+            let kept = existing_document.get(section).and_then(|entries| entries.get(key)).filter(|_| section != "services");
+            if merged_entry != Some(kept.unwrap_or(value)) {
+                return Err(RyujinError::Template(format!("Unable to merge {}.{} into the docker-compose.", section, key.as_str().unwrap_or_default())));
+            }
+        }
+    }
+
+    Ok((merged, report))
+}
+
+/// Shows how the generated entries are merged, and asks for a confirmation when keys of the file change.
+///
+/// # Arguments
+///
+/// * `report` - A reference to the `MergeReport` of `merge_compose`.
+/// * `output` - A reference to the `Output`, nothing is asked in a dry run.
+/// * `interactive` - A `bool` that indicates whether the user can be asked.
+/// * `get_input` - A function that returns a `Result<String, Error>`. This function is called to get the user's input.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if the user refuses the conflicting changes, and a `RyujinError::Io`
+/// if the input cannot be read.
+///
+/// # Example
+///
+/// ```
+/// confirm_merge(&report, &output, true, get_input)?;
+/// ```
+pub fn confirm_merge<F: FnMut() -> Result<String, Error>>(report: &MergeReport, output: &Output, interactive: bool, mut get_input: F) -> Result<(), RyujinError> {
+    if !report.added.is_empty() {
+        output.notice(&format!("Added to the docker-compose.yml: {}", report.added.join(", ")));
+    }
+    if !report.replaced.is_empty() {
+        output.notice(&format!("Replaced in the docker-compose.yml: {}", report.replaced.join(", ")));
+    }
+    if report.conflicts.is_empty() {
+        return Ok(());
+    }
+
+    output.notice("The following keys of the docker-compose.yml conflict with the generated ones:");
+    for conflict in &report.conflicts {
+        output.notice(&format!("- {}", conflict));
+    }
+    if !interactive || output.dry_run {
+        return Ok(());
+    }
+
+    output.notice("Write the merged docker-compose.yml? (y/n)");
+    let input = get_input()?;
+    if input.trim() == "y" {
+        Ok(())
+    } else {
+        Err(RyujinError::Input("The docker-compose.yml was kept, nothing was merged.".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXISTING: &str = "\
+# My stack, maintained by hand
+services:
+    # the database, do not touch
+    db:
+        image: postgres:16  # pinned
+        depends_on:
+            - nginx
+
+    nginx:
+        image: nginx:1.25
+        ports:
+            - \"80:80\"
+
+networks:
+    frontend:
+        driver: bridge
+
+x-notes: keep me
+";
+
+    fn generated(yaml: &str) -> ComposeFile {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn keeps_the_services_of_the_user() {
+        let (merged, report) = merge_compose(EXISTING, &generated("services:\n  portainer:\n    image: portainer/portainer-ce\n")).unwrap();
+        let document: Value = serde_yaml::from_str(&merged).unwrap();
+
+        assert_eq!(document["services"]["db"]["image"], Value::from("postgres:16"));
+        assert_eq!(document["services"]["nginx"]["image"], Value::from("nginx:1.25"));
+        assert_eq!(document["services"]["portainer"]["image"], Value::from("portainer/portainer-ce"));
+        assert_eq!(document["x-notes"], Value::from("keep me"));
+        assert_eq!(report.added, vec!["services.portainer"]);
+        assert!(report.replaced.is_empty());
+        assert!(report.conflicts.is_empty());
+    }
+
+    #[test]
+    fn keeps_the_comments_and_the_indentation() {
+        let (merged, _) = merge_compose(EXISTING, &generated("services:\n  portainer:\n    image: portainer/portainer-ce\n")).unwrap();
+
+        assert!(merged.starts_with("# My stack, maintained by hand\n"));
+        assert!(merged.contains("    # the database, do not touch\n"));
+        assert!(merged.contains("        image: postgres:16  # pinned\n"));
+        assert!(merged.contains("    portainer:\n        image: portainer/portainer-ce\n"));
+    }
+
+    #[test]
+    fn replaces_a_generated_service() {
+        let (merged, report) = merge_compose(EXISTING, &generated("services:\n  nginx:\n    image: nginx:alpine\n    ports:\n    - 80:80\n")).unwrap();
+        let document: Value = serde_yaml::from_str(&merged).unwrap();
+
+        assert_eq!(document["services"]["nginx"]["image"], Value::from("nginx:alpine"));
+        assert_eq!(document["services"]["db"]["depends_on"][0], Value::from("nginx"));
+        assert_eq!(report.replaced, vec!["services.nginx"]);
+        assert!(!merged.contains("nginx:1.25"));
+        // Merging the same services again changes nothing
+        let (again, report) = merge_compose(&merged, &generated("services:\n  nginx:\n    image: nginx:alpine\n    ports:\n    - 80:80\n")).unwrap();
+        assert_eq!(again, merged);
+        assert!(report.replaced.is_empty() && report.added.is_empty());
+    }
+
+    #[test]
+    fn reports_the_conflicting_keys() {
+        let compose = generated("services:\n  nginx:\n    image: nginx:alpine\nnetworks:\n  frontend:\n    internal: true\n  admin: {}\n");
+        let (merged, report) = merge_compose(EXISTING, &compose).unwrap();
+        let document: Value = serde_yaml::from_str(&merged).unwrap();
+
+        assert_eq!(report.conflicts, vec![
+            "services.nginx.image: replaced by the generated value",
+            "services.nginx.ports: replaced by the generated value",
+            "networks.frontend: the definition of the file is kept",
+        ]);
+        assert_eq!(report.added, vec!["networks.admin"]);
+        assert_eq!(document["networks"]["frontend"]["driver"], Value::from("bridge"));
+    }
+
+    #[test]
+    fn rejects_a_section_written_on_one_line() {
+        let existing = "services: {web: {image: nginx}}\n";
+        let result = merge_compose(existing, &generated("services:\n  portainer:\n    image: portainer/portainer-ce\n"));
+
+        assert!(matches!(result, Err(RyujinError::Input(_))));
+    }
+}
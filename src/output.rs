@@ -0,0 +1,142 @@
+use crate::error::RyujinError;
+use std::fs::{create_dir_all, read_to_string, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+
+/// `OutputFile` is a struct that represents a file generated by the compose command.
+///
+/// It contains the following fields:
+/// * `path`: A `PathBuf` that represents the path of the file.
+/// * `content`: A `String` that holds the content of the file.
+/// * `mode`: An `Option<u32>` that represents the Unix permissions of the file, e.g. `0o600` for the private keys.
+///   The default permissions are used when it is `None`.
+/// * `existed`: A `bool` that indicates whether the file existed before, and is replaced.
+#[derive(Debug, Clone)]
+pub struct OutputFile {
+    pub path: PathBuf,
+    pub content: String,
+    pub mode: Option<u32>,
+    pub existed: bool,
+}
+
+/// `Output` is a struct that writes the files generated by the compose command, or only renders them.
+///
+/// It contains the following fields:
+/// * `dry_run`: A `bool` that indicates whether the files are rendered without being written (`--dry-run` or `--stdout`).
+/// * `stdout`: A `bool` that indicates whether the `docker-compose.yml` is printed on the standard output (`--stdout`).
+///   The other messages are printed on the standard error then, so the output can be piped.
+/// * `files`: A `Vec<OutputFile>` that contains the files generated so far, in order.
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    pub dry_run: bool,
+    pub stdout: bool,
+    pub files: Vec<OutputFile>,
+}
+
+impl Output {
+    /// Creates an `Output`. `--stdout` implies `--dry-run`, since the files are printed instead of being written.
+    pub fn new(dry_run: bool, stdout: bool) -> Self {
+        Output { dry_run: dry_run || stdout, stdout, files: Vec::new() }
+    }
+
+    /// Writes a file, or records it without writing it in a dry run.
+    ///
+    /// The parent directories are created if needed. On Unix, the permissions are set when the file is created, so a
+    /// private key is never readable by other users, even for a moment.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    /// * `content` - The content of the file.
+    /// * `mode` - The permissions of the file, or `None` for the default permissions.
+    ///
+    /// # Errors
+    ///
+    /// This function will return a `RyujinError::Io` if the file or its directory cannot be created or written.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// output.write(Path::new("./output/.env"), "DB_PASSWORD=secret\n", Some(0o600))?;
+    /// ```
+    pub fn write(&mut self, path: &Path, content: &str, mode: Option<u32>) -> Result<(), RyujinError> {
+        let existed = self.exists(path);
+        self.files.retain(|file| file.path != path);
+        self.files.push(OutputFile { path: path.to_path_buf(), content: content.to_string(), mode, existed });
+        if self.dry_run {
+            return Ok(());
+        }
+
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            create_dir_all(parent)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+        let mut file = options.open(path)?;
+        // The mode of `open` only applies to new files
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        }
+        file.write_all(content.as_bytes())?;
+        Ok(())
+    }
+
+    /// Tells whether a file exists, on the disk or among the files rendered so far.
+    pub fn exists(&self, path: &Path) -> bool {
+        self.files.iter().any(|file| file.path == path) || path.is_file()
+    }
+
+    /// Reads a file, from the files rendered so far or from the disk.
+    ///
+    /// # Errors
+    ///
+    /// This function will return a `RyujinError::Io` if the file was not rendered and cannot be read.
+    pub fn read(&self, path: &Path) -> Result<String, RyujinError> {
+        match self.files.iter().find(|file| file.path == path) {
+            Some(file) => Ok(file.content.clone()),
+            None => Ok(read_to_string(path)?),
+        }
+    }
+
+    /// Prints a message or a question for the user, on the standard error with `--stdout` so the standard output only
+    /// holds the docker-compose.yml.
+    pub fn notice(&self, message: &str) {
+        if self.stdout {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    /// Prints what was rendered in a dry run.
+    ///
+    /// With `--stdout`, the `docker-compose.yml` is printed on the standard output and the list of the files on the
+    /// standard error. With `--dry-run`, the list of the files is printed, with their permissions and their size.
+    /// The content of the other files is never printed, since the `.env` file and the private keys hold secrets.
+    pub fn print(&self) {
+        if !self.dry_run {
+            return;
+        }
+
+        if self.stdout {
+            if let Some(compose) = self.files.iter().find(|file| file.path.file_name().is_some_and(|name| name == "docker-compose.yml")) {
+                print!("{}", compose.content);
+            }
+        }
+
+        self.notice("Dry run, nothing was written. The following files would be written:");
+        for file in &self.files {
+            let mode = file.mode.map(|mode| format!("{:04o}", mode)).unwrap_or_else(|| "----".to_string());
+            let action = if file.existed { "replace" } else { "create" };
+            self.notice(&format!("{} {:>8} bytes  {:<7}  {}", mode, file.content.len(), action, file.path.display()));
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use crate::generic::{Services, Service, PortMapping};
+use crate::error::RyujinError;
+use crate::output::Output;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Error;
+
+
+/// `PortRemap` is a struct that represents a host port given on the command line for a port of a service.
+///
+/// It contains the following fields:
+/// * `service`: A `String` that represents the name of the service.
+/// * `port`: A `String` that represents the name of the port in the `ports` of the service.
+/// * `host`: A `u16` that represents the new host port.
+#[derive(Debug, Clone)]
+pub struct PortRemap {
+    pub service: String,
+    pub port: String,
+    pub host: u16,
+}
+
+/// `PortConflict` is a struct that represents a host port published by several selected services.
+///
+/// It contains the following fields:
+/// * `host`: A `u16` that represents the host port.
+/// * `owners`: A `Vec<(String, String)>` that contains the service and the port name of each mapping publishing the host port.
+#[derive(Debug, Clone)]
+pub struct PortConflict {
+    pub host: u16,
+    pub owners: Vec<(String, String)>,
+}
+
+/// Parses a port remap given as `<service>.<port>=<host port>`, e.g. `nginx.http=8081`.
+///
+/// # Arguments
+///
+/// * `value` - The value of the `--port` option.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if the value does not follow the `<service>.<port>=<host port>` format.
+///
+/// # Example
+///
+/// ```
+/// let remap = parse_port_remap("nginx.http=8081").unwrap();
+/// ```
+pub fn parse_port_remap(value: &str) -> Result<PortRemap, RyujinError> {
+    let invalid = || RyujinError::Input(format!("Invalid port remap {}. Use <service>.<port>=<host port>, e.g. nginx.http=8081.", value));
+
+    let (target, host) = value.split_once('=').ok_or_else(invalid)?;
+    let (service, port) = target.rsplit_once('.').ok_or_else(invalid)?;
+    let host = host.trim().parse::<u16>().ok().filter(|host| *host > 0).ok_or_else(invalid)?;
+
+    Ok(PortRemap { service: service.trim().to_string(), port: port.trim().to_string(), host })
+}
+
+/// Returns the host port of a mapping, read from the catalog or from the answer of its question.
+///
+/// `None` is returned when the question of the mapping was skipped or has no valid answer.
+pub fn host_port(service: &Service, mapping: &PortMapping) -> Option<u16> {
+    match &mapping.variable {
+        Some(variable) => service.questions.iter()
+            .find(|question| &question.variable == variable)
+            .and_then(|question| question.answer.as_ref())
+            .and_then(|answer| answer.parse::<u16>().ok()),
+        None => mapping.host,
+    }
+}
+
+/// Returns the host port of each port of a service, keyed by port name.
+///
+/// This is the `ports` map available to the templates, e.g. `{{ ports.http }}`.
+pub fn host_ports(service: &Service) -> HashMap<String, u16> {
+    service.ports.iter()
+        .filter_map(|mapping| host_port(service, mapping).map(|host| (mapping.name.clone(), host)))
+        .collect()
+}
+
+/// Changes the host port of a port of a service.
+///
+/// When the host port comes from a question, the answer of the question is changed so the template and the readme agree.
+fn set_host_port(service: &mut Service, port: &str, host: u16) -> Result<(), RyujinError> {
+    let mapping = service.ports.iter_mut()
+        .find(|mapping| mapping.name == port)
+        .ok_or_else(|| RyujinError::Input(format!("The service {} has no port named {}.", service.name, port)))?;
+
+    match mapping.variable.clone() {
+        Some(variable) => {
+            if let Some(question) = service.questions.iter_mut().find(|question| question.variable == variable) {
+                question.answer = Some(host.to_string());
+            }
+        },
+        None => mapping.host = Some(host),
+    }
+    Ok(())
+}
+
+/// Applies the port remaps given on the command line to the selected services.
+///
+/// # Arguments
+///
+/// * `services` - A mutable reference to the selected `Services`.
+/// * `remaps` - A slice of `PortRemap` parsed from the `--port` options.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if a remap targets a service that is not selected or a port that does not exist.
+pub fn apply_remaps(services: &mut Services, remaps: &[PortRemap]) -> Result<(), RyujinError> {
+    for remap in remaps {
+        let service = services.get_mut(&remap.service)
+            .ok_or_else(|| RyujinError::Input(format!("Cannot remap {}.{}: the service {} is not selected.", remap.service, remap.port, remap.service)))?;
+        set_host_port(service, &remap.port, remap.host)?;
+    }
+    Ok(())
+}
+
+/// Collects the host ports published by several selected services.
+///
+/// # Arguments
+///
+/// * `services` - A reference to the selected `Services`.
+///
+/// # Returns
+///
+/// A `Vec<PortConflict>` sorted by host port. The owners of each conflict are sorted by service name.
+pub fn find_conflicts(services: &Services) -> Vec<PortConflict> {
+    let mut published: BTreeMap<u16, Vec<(String, String)>> = BTreeMap::new();
+
+    for (name, service) in services {
+        for mapping in &service.ports {
+            if let Some(host) = host_port(service, mapping) {
+                published.entry(host).or_default().push((name.clone(), mapping.name.clone()));
+            }
+        }
+    }
+
+    published.into_iter()
+        .filter(|(_, owners)| owners.len() > 1)
+        .map(|(host, mut owners)| {
+            owners.sort();
+            PortConflict { host, owners }
+        })
+        .collect()
+}
+
+/// Returns the first host port after `from` that is not in `used`.
+fn next_free_port(from: u16, used: &HashSet<u16>) -> u16 {
+    let mut candidate = from;
+    loop {
+        candidate = if candidate == u16::MAX { 1024 } else { candidate + 1 };
+        if !used.contains(&candidate) {
+            return candidate;
+        }
+    }
+}
+
+/// Resolves the host port conflicts between the selected services.
+///
+/// The first owner of a conflicting port, in alphabetical order, keeps it. When the standard input is a terminal,
+/// a new host port is asked for each other owner, with the next free port as default. Otherwise the conflicts are
+/// reported and nothing is remapped.
+///
+/// # Arguments
+///
+/// * `services` - A mutable reference to the selected `Services`.
+/// * `interactive` - A `bool` that indicates whether the user can be asked for new host ports.
+/// * `output` - A reference to the `Output`, which prints the questions on the standard error with `--stdout`.
+/// * `get_input` - A function that returns a `Result<String, Error>`. This function is called to get the user's input.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if some conflicts remain and the user cannot be asked,
+/// and a `RyujinError::Io` if an I/O error occurs while reading the input.
+///
+/// # Example
+///
+/// ```
+/// resolve_conflicts(&mut selected_services, true, &Output::default(), get_input).unwrap();
+/// ```
+pub fn resolve_conflicts<F: FnMut() -> Result<String, Error>>(services: &mut Services, interactive: bool, output: &Output, mut get_input: F) -> Result<(), RyujinError> {
+    let conflicts = find_conflicts(services);
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    if !interactive {
+        let report: Vec<String> = conflicts.iter()
+            .map(|conflict| {
+                let owners: Vec<String> = conflict.owners.iter().map(|(service, port)| format!("{}.{}", service, port)).collect();
+                format!("host port {} is published by {}", conflict.host, owners.join(", "))
+            })
+            .collect();
+        return Err(RyujinError::Input(format!("The selected services publish the same host ports:\n{}\nRemap them with --port <service>.<port>=<host port>.", report.join("\n"))));
+    }
+
+    let mut used: HashSet<u16> = services.values()
+        .flat_map(|service| service.ports.iter().filter_map(|mapping| host_port(service, mapping)))
+        .collect();
+
+    for conflict in conflicts {
+        let (kept_service, kept_port) = &conflict.owners[0];
+        for (service, port) in &conflict.owners[1..] {
+            let suggestion = next_free_port(conflict.host, &used);
+            let host = loop {
+                output.notice(&format!("The host port {} of {}.{} is already published by {}.{}.", conflict.host, service, port, kept_service, kept_port));
+                output.notice(&format!("Enter a new host port [default: {}]:", suggestion));
+                let input = get_input()?;
+                let input = input.trim();
+                if input.is_empty() {
+                    break suggestion;
+                }
+                match input.parse::<u16>() {
+                    Ok(host) if host > 0 && !used.contains(&host) => break host,
+                    Ok(host) if host > 0 => output.notice(&format!("The host port {} is already published.\n", host)),
+                    _ => output.notice("The value must be a port number between 1 and 65535.\n"),
+                }
+            };
+            used.insert(host);
+            let service = services.get_mut(service).expect("The owners of a conflict are selected services");
+            set_host_port(service, port, host)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_to_string;
+
+    fn selection(names: &[&str]) -> Services {
+        let path = format!("{}/services/services.json", env!("CARGO_MANIFEST_DIR"));
+        let catalog: Services = serde_json::from_str(&read_to_string(path).unwrap()).unwrap();
+        names.iter().map(|name| (name.to_string(), catalog[*name].clone())).collect()
+    }
+
+    fn conflicts(services: &Services) -> Vec<(u16, Vec<(String, String)>)> {
+        find_conflicts(services).into_iter().map(|conflict| (conflict.host, conflict.owners)).collect()
+    }
+
+    fn owner(service: &str, port: &str) -> (String, String) {
+        (service.to_string(), port.to_string())
+    }
+
+    #[test]
+    fn remaps_are_parsed() {
+        let remap = parse_port_remap("site-a.http = 8081").unwrap();
+        assert_eq!((remap.service.as_str(), remap.port.as_str(), remap.host), ("site-a", "http", 8081));
+        for value in ["nginx.http", "nginx=8081", "nginx.http=0", "nginx.http=65536", "nginx.http=web"] {
+            assert!(matches!(parse_port_remap(value), Err(RyujinError::Input(_))), "{}", value);
+        }
+    }
+
+    #[test]
+    fn services_publishing_the_same_host_port_conflict() {
+        let services = selection(&["apache", "nginx", "portainer"]);
+        assert_eq!(conflicts(&services), vec![
+            (443, vec![owner("apache", "https"), owner("nginx", "https")]),
+            (8080, vec![owner("apache", "http"), owner("nginx", "http")]),
+        ]);
+    }
+
+    #[test]
+    fn remaps_move_the_host_ports() {
+        let mut services = selection(&["apache", "nginx", "portainer"]);
+        let remaps = ["nginx.http=8081", "nginx.https=8443", "portainer.ui=9444"].map(|remap| parse_port_remap(remap).unwrap());
+        apply_remaps(&mut services, &remaps).unwrap();
+
+        assert!(find_conflicts(&services).is_empty());
+        assert_eq!(host_ports(&services["nginx"]), HashMap::from([("http".to_string(), 8081), ("https".to_string(), 8443)]));
+        // A port asked by a question is remapped through the answer of the question
+        let ui_port = services["portainer"].questions.iter().find(|question| question.variable == "ui_port").unwrap();
+        assert_eq!(ui_port.answer.as_deref(), Some("9444"));
+    }
+
+    #[test]
+    fn an_invalid_remap_is_rejected() {
+        let mut services = selection(&["nginx"]);
+        for remap in ["apache.http=8081", "nginx.ssh=2222"] {
+            let error = apply_remaps(&mut services, &[parse_port_remap(remap).unwrap()]).unwrap_err();
+            assert!(matches!(error, RyujinError::Input(_)), "{}", error);
+        }
+    }
+
+    #[test]
+    fn conflicts_are_reported_without_a_terminal() {
+        let mut services = selection(&["apache", "nginx"]);
+        let error = resolve_conflicts(&mut services, false, &Output::default(), || panic!("no question is asked")).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid input: The selected services publish the same host ports:\n\
+            host port 443 is published by apache.https, nginx.https\n\
+            host port 8080 is published by apache.http, nginx.http\n\
+            Remap them with --port <service>.<port>=<host port>.");
+    }
+
+    #[test]
+    fn conflicts_are_resolved_with_the_answers() {
+        let mut services = selection(&["apache", "nginx"]);
+        // The suggested port for 443, then a used port, an invalid port and a free port for 8080
+        let mut inputs = vec!["\n", "443\n", "http\n", "8081\n"].into_iter();
+        resolve_conflicts(&mut services, true, &Output::new(false, true), || Ok(inputs.next().expect("an answer is left").to_string())).unwrap();
+
+        assert_eq!(inputs.next(), None);
+        assert!(find_conflicts(&services).is_empty());
+        assert_eq!(host_ports(&services["apache"]), HashMap::from([("http".to_string(), 8080), ("https".to_string(), 443)]));
+        assert_eq!(host_ports(&services["nginx"]), HashMap::from([("http".to_string(), 8081), ("https".to_string(), 444)]));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::generic::{Services, Service};
+use crate::error::RyujinError;
+use crate::instances::instances_of;
+use regex::Regex;
+
+
+/// Parses a service of the selection, given as `<service>` or `<service>@<profile>`, e.g. `portainer@admin`.
+///
+/// Several profiles are separated by `+`, e.g. `pwn@ctf+debug`.
+///
+/// # Arguments
+///
+/// * `choice` - A service of the selection, from `--services` or from the current selection.
+///
+/// # Returns
+///
+/// * `Ok((String, Vec<String>))` - The name of the service and its profiles, empty when the service always starts.
+/// * `Err(RyujinError)` - If a profile is not a valid profile name.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if a profile name is empty or contains other characters than
+/// letters, digits, `_`, `.` and `-`, which Compose rejects.
+///
+/// # Example
+///
+/// ```
+/// let (service, profiles) = parse_choice("portainer@admin").unwrap();
+/// ```
+pub fn parse_choice(choice: &str) -> Result<(String, Vec<String>), RyujinError> {
+    let Some((service, profiles)) = choice.split_once('@') else {
+        return Ok((choice.trim().to_string(), Vec::new()));
+    };
+
+    let profile_name = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9_.-]*$").expect("The profile pattern is valid");
+    let mut parsed: Vec<String> = Vec::new();
+    for profile in profiles.split('+').map(str::trim) {
+        if !profile_name.is_match(profile) {
+            return Err(RyujinError::Input(format!("Invalid profile {:?} in {}. Use <service>@<profile>, e.g. portainer@admin, with profiles made of letters, digits, _, . and -.", profile, choice)));
+        }
+        if !parsed.iter().any(|known| known == profile) {
+            parsed.push(profile.to_string());
+        }
+    }
+
+    Ok((service.trim().to_string(), parsed))
+}
+
+/// Tells whether a service is sure to find a dependency started, whatever the profiles enabled.
+///
+/// This is the case when the dependency always starts, or when the service only starts with profiles the dependency
+/// belongs to as well.
+pub fn can_depend_on(service: &Service, dependency: &Service) -> bool {
+    dependency.profiles.is_empty()
+        || (!service.profiles.is_empty() && service.profiles.iter().all(|profile| dependency.profiles.contains(profile)))
+}
+
+/// Checks that the services of the selection can start with their required services.
+///
+/// # Arguments
+///
+/// * `services` - A reference to the selected `Services`, with their profiles.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` listing the services that require a service of another profile,
+/// since Compose refuses a `depends_on` on a service whose profiles are not enabled.
+///
+/// # Example
+///
+/// ```
+/// check_profiles(&selected_services)?;
+/// ```
+pub fn check_profiles(services: &Services) -> Result<(), RyujinError> {
+    let mut problems: Vec<String> = Vec::new();
+    for (name, service) in services {
+        for required_name in service.requires.iter().flat_map(|required| instances_of(services, required)) {
+            let required = &services[&required_name];
+            if !can_depend_on(service, required) {
+                problems.push(format!("{} requires {}, which only starts with the profiles {}", name, required_name, required.profiles.join(", ")));
+            }
+        }
+    }
+
+    if !problems.is_empty() {
+        problems.sort();
+        return Err(RyujinError::Input(format!("Some services require a service they cannot start with:\n{}\nSelect the required services without profile, or with the profiles of the services that require them.", problems.join("\n"))));
+    }
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use crate::generic::{Services, ResourceLimits, Size};
+use crate::error::RyujinError;
+use regex::Regex;
+use std::collections::HashMap;
+
+
+/// The resource limits that can be overridden with `--limit`.
+pub const SETTINGS: [&str; 3] = ["cpus", "memory", "pids"];
+
+/// `LimitOverride` is a struct that represents a resource limit of a service given on the command line.
+///
+/// It contains the following fields:
+/// * `service`: A `String` that represents the name of the service.
+/// * `setting`: A `String` that represents the limit, one of `SETTINGS`.
+/// * `value`: A `String` that represents the new value of the limit.
+#[derive(Debug, Clone)]
+pub struct LimitOverride {
+    pub service: String,
+    pub setting: String,
+    pub value: String,
+}
+
+/// Tells whether a value is a number of CPUs, e.g. `0.5` or `2`.
+pub fn is_cpus(value: &str) -> bool {
+    value.parse::<f64>().is_ok_and(|cpus| cpus > 0.0)
+}
+
+/// Tells whether a value is an amount of memory, e.g. `512m` or `1g`.
+pub fn is_memory(value: &str) -> bool {
+    let memory = Regex::new(r"^[0-9]+[bkmgBKMG]?$").expect("The memory pattern is valid");
+    memory.is_match(value) && !value.starts_with('0')
+}
+
+/// Checks the value of a resource limit.
+fn check_setting(setting: &str, value: &str) -> Result<(), String> {
+    match setting {
+        "cpus" if !is_cpus(value) => Err(format!("{} is not a number of CPUs, e.g. 0.5 or 2", value)),
+        "memory" if !is_memory(value) => Err(format!("{} is not an amount of memory, e.g. 512m or 1g", value)),
+        "pids" if value.parse::<u32>().map_or(true, |pids| pids == 0) => Err(format!("{} is not a number of processes", value)),
+        "cpus" | "memory" | "pids" => Ok(()),
+        _ => Err(format!("{} is not a resource limit, use one of {}", setting, SETTINGS.join(", "))),
+    }
+}
+
+/// Parses a resource limit given as `<service>.<limit>=<value>`, e.g. `pwn.memory=2g`.
+///
+/// # Arguments
+///
+/// * `value` - The value of the `--limit` option.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if the value does not follow the `<service>.<limit>=<value>` format,
+/// if the limit is unknown or if its value is not valid.
+///
+/// # Example
+///
+/// ```
+/// let limit = parse_limit_override("pwn.memory=2g").unwrap();
+/// ```
+pub fn parse_limit_override(value: &str) -> Result<LimitOverride, RyujinError> {
+    let invalid = || RyujinError::Input(format!("Invalid resource limit {}. Use <service>.<limit>=<value>, e.g. pwn.memory=2g.", value));
+
+    let (target, limit_value) = value.split_once('=').ok_or_else(invalid)?;
+    let (service, setting) = target.rsplit_once('.').ok_or_else(invalid)?;
+    let (service, setting, limit_value) = (service.trim(), setting.trim(), limit_value.trim());
+    check_setting(setting, limit_value)
+        .map_err(|e| RyujinError::Input(format!("Invalid resource limit {}: {}.", value, e)))?;
+
+    Ok(LimitOverride { service: service.to_string(), setting: setting.to_string(), value: limit_value.to_string() })
+}
+
+/// Computes the resource limits of the selected services.
+///
+/// Each service gets the limits recommended by the catalog for the size preset, then the limits given with `--limit`
+/// replace them.
+///
+/// # Arguments
+///
+/// * `services` - A reference to the selected `Services`.
+/// * `size` - The `Size` preset given with `--size`.
+/// * `overrides` - A slice of `LimitOverride` parsed from the `--limit` options.
+///
+/// # Returns
+///
+/// * `Ok(HashMap<String, ResourceLimits>)` - The limits of the selected services, keyed by service name. A service without limits is not listed.
+/// * `Err(RyujinError)` - If an override targets a service that is not selected.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Input` if an override targets a service that is not selected.
+///
+/// # Example
+///
+/// ```
+/// let limits = resolve_limits(&selected_services, Size::Small, &[]).unwrap();
+/// ```
+pub fn resolve_limits(services: &Services, size: Size, overrides: &[LimitOverride]) -> Result<HashMap<String, ResourceLimits>, RyujinError> {
+    for limit in overrides {
+        if !services.contains_key(&limit.service) {
+            return Err(RyujinError::Input(format!("Cannot limit {}.{}: the service {} is not selected.", limit.service, limit.setting, limit.service)));
+        }
+    }
+
+    let mut resolved: HashMap<String, ResourceLimits> = HashMap::new();
+    for name in services.keys() {
+        let mut limits = services[name].resources.get(&size).cloned().unwrap_or_default();
+        for limit in overrides.iter().filter(|limit| &limit.service == name) {
+            match limit.setting.as_str() {
+                "cpus" => limits.cpus = Some(limit.value.clone()),
+                "memory" => limits.memory = Some(limit.value.clone()),
+                "pids" => limits.pids = limit.value.parse().ok(),
+                _ => {},
+            }
+        }
+        if limits.cpus.is_some() || limits.memory.is_some() || limits.pids.is_some() {
+            resolved.insert(name.clone(), limits);
+        }
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_to_string;
+
+    fn catalog() -> Services {
+        let path = format!("{}/services/services.json", env!("CARGO_MANIFEST_DIR"));
+        serde_json::from_str(&read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn selection(names: &[&str]) -> Services {
+        let catalog = catalog();
+        names.iter().map(|name| (name.to_string(), catalog[*name].clone())).collect()
+    }
+
+    #[test]
+    fn cpus_are_positive_numbers() {
+        assert!(is_cpus("0.5"));
+        assert!(is_cpus("2"));
+        assert!(!is_cpus("0"));
+        assert!(!is_cpus("-1"));
+        assert!(!is_cpus("a"));
+    }
+
+    #[test]
+    fn memory_is_a_positive_amount_with_a_unit() {
+        assert!(is_memory("512m"));
+        assert!(is_memory("1g"));
+        assert!(is_memory("1024"));
+        assert!(!is_memory("0m"));
+        assert!(!is_memory("1.5g"));
+        assert!(!is_memory("1t"));
+        assert!(!is_memory("m"));
+    }
+
+    #[test]
+    fn a_limit_override_is_parsed() {
+        let limit = parse_limit_override("api.v2.memory = 2g").unwrap();
+        assert_eq!((limit.service.as_str(), limit.setting.as_str(), limit.value.as_str()), ("api.v2", "memory", "2g"));
+    }
+
+    #[test]
+    fn an_invalid_limit_override_is_an_input_error() {
+        for value in ["pwn.memory", "pwn=2g", "pwn.disk=2g", "pwn.memory=2t", "pwn.cpus=0", "pwn.pids=0", "pwn.pids=-1"] {
+            assert!(matches!(parse_limit_override(value), Err(RyujinError::Input(_))), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn the_size_preset_is_applied() {
+        let limits = resolve_limits(&selection(&["nginx", "pwn"]), Size::Small, &[]).unwrap();
+        assert_eq!(limits["nginx"].memory.as_deref(), Some("128m"));
+        assert_eq!(limits["pwn"].cpus.as_deref(), Some("0.5"));
+        assert_eq!(limits["pwn"].pids, Some(64));
+    }
+
+    #[test]
+    fn a_limit_override_beats_the_preset() {
+        let overrides = [parse_limit_override("pwn.memory=2g").unwrap(), parse_limit_override("pwn.pids=32").unwrap()];
+        let limits = resolve_limits(&selection(&["nginx", "pwn"]), Size::Small, &overrides).unwrap();
+        assert_eq!(limits["pwn"].memory.as_deref(), Some("2g"));
+        assert_eq!(limits["pwn"].pids, Some(32));
+        assert_eq!(limits["pwn"].cpus.as_deref(), Some("0.5"));
+        assert_eq!(limits["nginx"].memory.as_deref(), Some("128m"));
+    }
+
+    #[test]
+    fn a_limit_override_of_an_unselected_service_is_an_input_error() {
+        let overrides = [parse_limit_override("pwn.memory=2g").unwrap()];
+        let result = resolve_limits(&selection(&["nginx"]), Size::Medium, &overrides);
+        assert!(matches!(result, Err(RyujinError::Input(_))));
+    }
+
+    #[test]
+    fn a_service_without_limits_is_not_listed() {
+        let mut services = selection(&["nginx"]);
+        services.get_mut("nginx").unwrap().resources.clear();
+        assert!(resolve_limits(&services, Size::Large, &[]).unwrap().is_empty());
+    }
+}
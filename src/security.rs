@@ -0,0 +1,132 @@
+use crate::generic::{Service, SecurityOption};
+use crate::compose_model::Hardening;
+
+
+/// `SecurityLevel` is an enum that represents the security profile chosen with `--security`.
+///
+/// * `None`: No hardening option is added.
+/// * `Baseline`: The processes cannot gain privileges and every capability is dropped, except the `cap_add` of the catalog.
+/// * `Strict`: The baseline, plus a read-only root filesystem with the `tmpfs` of the catalog, and the non-root `user` of the catalog.
+///
+/// The options a service opts out of in the catalog are never added.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SecurityLevel {
+    None,
+    #[default]
+    Baseline,
+    Strict,
+}
+
+/// Tells whether a hardening option applies to a service at a security level.
+fn applies(service: &Service, level: SecurityLevel, option: SecurityOption) -> bool {
+    let in_level = match option {
+        SecurityOption::NoNewPrivileges | SecurityOption::CapDrop => level != SecurityLevel::None,
+        SecurityOption::ReadOnly | SecurityOption::User => level == SecurityLevel::Strict,
+    };
+    in_level && !service.security.opt_out.contains(&option)
+}
+
+/// Computes the hardening options of a service for a security level.
+///
+/// # Arguments
+///
+/// * `service` - A reference to the `Service`, for its `security` entry in the catalog.
+/// * `level` - The `SecurityLevel` chosen with `--security`.
+///
+/// # Returns
+///
+/// The `Hardening` options to add to the compose services of the template of the service.
+///
+/// # Example
+///
+/// ```
+/// let hardening = hardening_for(&service, SecurityLevel::Strict);
+/// compose.harden(&compose_services, &hardening);
+/// ```
+pub fn hardening_for(service: &Service, level: SecurityLevel) -> Hardening {
+    let mut hardening = Hardening::default();
+
+    if applies(service, level, SecurityOption::NoNewPrivileges) {
+        hardening.security_opt.push("no-new-privileges:true".to_string());
+    }
+    if applies(service, level, SecurityOption::CapDrop) {
+        hardening.cap_drop.push("ALL".to_string());
+        hardening.cap_add = service.security.cap_add.clone();
+    }
+    if applies(service, level, SecurityOption::ReadOnly) {
+        hardening.read_only = true;
+        hardening.tmpfs = service.security.tmpfs.clone();
+    }
+    if applies(service, level, SecurityOption::User) {
+        hardening.user = service.security.user.clone();
+    }
+
+    hardening
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::Services;
+    use std::fs::read_to_string;
+
+    fn catalog() -> Services {
+        let path = format!("{}/services/services.json", env!("CARGO_MANIFEST_DIR"));
+        serde_json::from_str(&read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn strict_adds_every_option_of_the_catalog() {
+        let hardening = hardening_for(&catalog()["nginx"], SecurityLevel::Strict);
+        assert_eq!(hardening.security_opt, ["no-new-privileges:true"]);
+        assert_eq!(hardening.cap_drop, ["ALL"]);
+        assert_eq!(hardening.cap_add, ["NET_BIND_SERVICE"]);
+        assert!(hardening.read_only);
+        assert_eq!(hardening.tmpfs, ["/var/cache/nginx", "/tmp"]);
+        assert_eq!(hardening.user.as_deref(), Some("nginx"));
+    }
+
+    #[test]
+    fn strict_skips_the_options_a_service_opts_out_of() {
+        let hardening = hardening_for(&catalog()["pwn"], SecurityLevel::Strict);
+        assert!(!hardening.read_only);
+        assert!(hardening.tmpfs.is_empty());
+        assert_eq!(hardening.user, None);
+        assert_eq!(hardening.security_opt, ["no-new-privileges:true"]);
+        assert_eq!(hardening.cap_drop, ["ALL"]);
+        assert_eq!(hardening.cap_add, ["SYS_PTRACE"]);
+    }
+
+    #[test]
+    fn baseline_neither_sets_a_read_only_filesystem_nor_a_user() {
+        let hardening = hardening_for(&catalog()["nginx"], SecurityLevel::Baseline);
+        assert_eq!(hardening.security_opt, ["no-new-privileges:true"]);
+        assert_eq!(hardening.cap_drop, ["ALL"]);
+        assert_eq!(hardening.cap_add, ["NET_BIND_SERVICE"]);
+        assert!(!hardening.read_only);
+        assert!(hardening.tmpfs.is_empty());
+        assert_eq!(hardening.user, None);
+    }
+
+    #[test]
+    fn none_adds_no_option() {
+        for service in catalog().values() {
+            let hardening = hardening_for(service, SecurityLevel::None);
+            assert!(hardening.security_opt.is_empty() && hardening.cap_drop.is_empty() && hardening.cap_add.is_empty());
+            assert!(!hardening.read_only && hardening.tmpfs.is_empty() && hardening.user.is_none());
+        }
+    }
+
+    #[test]
+    fn the_user_of_the_catalog_is_skipped_when_opted_out() {
+        let services = catalog();
+        for (name, service) in &services {
+            let hardening = hardening_for(service, SecurityLevel::Strict);
+            if service.security.opt_out.contains(&SecurityOption::User) {
+                assert_eq!(hardening.user, None, "{} opts out of the user", name);
+            } else {
+                assert_eq!(hardening.user, service.security.user, "{} keeps its user", name);
+            }
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use crate::generic::Services;
-use std::io::{Error, ErrorKind};
+use crate::error::RyujinError;
+use crate::profiles::parse_choice;
+use crate::instances::parse_instance;
 use std::collections::HashSet;
 use clap::ArgMatches;
 
@@ -8,6 +10,8 @@ use clap::ArgMatches;
 ///
 /// This function takes a vector of service names and a `Services` object that represents the catalog of available services.
 /// It checks if each service in the vector exists in the catalog. If a service does not exist, it returns an error.
+/// A service can be followed by an instance name, e.g. `nginx:site-a`, and by its Compose profiles, e.g. `portainer@admin`,
+/// which must be valid names.
 ///
 /// # Arguments
 ///
@@ -16,7 +20,8 @@ use clap::ArgMatches;
 ///
 /// # Errors
 ///
-/// This function will return an error if a service in the vector does not exist in the catalog.
+/// This function will return a `RyujinError::Input` if a service in the vector does not exist in the catalog, or if an
+/// instance or a profile name is invalid, like the compose command does.
 ///
 /// # Example
 ///
@@ -25,13 +30,15 @@ use clap::ArgMatches;
 /// let catalog = load_services();
 /// select_check_catalog(&services, &catalog);
 /// ```
-fn select_check_catalog(service: &Vec<String>, catalog : &Services) -> Result<(), Error>{
+fn select_check_catalog(service: &[String], catalog : &Services) -> Result<(), RyujinError>{
     
-    let catalog_keys: HashSet<String> = catalog.keys().map(|k| k.clone()).collect();
+    let catalog_keys: HashSet<String> = catalog.keys().cloned().collect();
 
     for item in service.iter() {
-        if !catalog_keys.contains(item) { 
-            return Err(Error::new(ErrorKind::InvalidData, format!("Requested service {} is not in the available services.", item)));
+        let (name, _) = parse_choice(item)?;
+        let (name, _) = parse_instance(&name)?;
+        if !catalog_keys.contains(&name) { 
+            return Err(RyujinError::Input(format!("Requested service {} is not in the available services.", name)));
         }
     }
 
@@ -60,10 +67,10 @@ fn select_check_catalog(service: &Vec<String>, catalog : &Services) -> Result<()
 ///     Err(e) => println!("Error erasing selection: {}", e),
 /// }
 /// ```
-fn erase_current_selection(user_selection: &mut Vec<String>) -> Result<(), Error>{
+fn erase_current_selection(user_selection: &mut Vec<String>) -> Result<(), RyujinError>{
     
     match user_selection.is_empty(){
-        true => return Err(Error::new(ErrorKind::InvalidInput, "Selection is empty.")),
+        true => return Err(RyujinError::Selection("Selection is empty.".to_string())),
         false => user_selection.clear(),
     }
 
@@ -84,7 +91,7 @@ fn erase_current_selection(user_selection: &mut Vec<String>) -> Result<(), Error
 ///
 /// # Returns
 ///
-/// * `Result<(), Error>` - Returns `Ok(())` if the operation is successful, otherwise returns an `Error`.
+/// * `Result<(), RyujinError>` - Returns `Ok(())` if the operation is successful, otherwise returns a `RyujinError::Selection`.
 ///
 /// # Examples
 ///
@@ -93,7 +100,7 @@ fn erase_current_selection(user_selection: &mut Vec<String>) -> Result<(), Error
 /// let new_services = vec!["service3".to_string(), "service4".to_string()];
 /// add_to_current_selection(&mut user_selection, &new_services);
 /// ```
-fn add_to_current_selection(user_selection: &mut Vec<String>, new_services: &Vec<String>) -> Result<(), Error> {
+fn add_to_current_selection(user_selection: &mut Vec<String>, new_services: &[String]) -> Result<(), RyujinError> {
 
     for item in new_services.iter(){
         if !user_selection.contains(item){
@@ -110,6 +117,8 @@ fn add_to_current_selection(user_selection: &mut Vec<String>, new_services: &Vec
 ///
 /// This function checks if the user selection is empty. If it is, it returns an error.
 /// If the user selection is not empty, it iterates over the services to remove and removes them from the user selection.
+/// A service given without profile is removed whatever the profiles it was selected with, and a service given without
+/// instance is removed with all its instances, e.g. `nginx` removes `nginx:site-a`.
 ///
 /// # Arguments
 ///
@@ -130,16 +139,21 @@ fn add_to_current_selection(user_selection: &mut Vec<String>, new_services: &Vec
 ///     Err(e) => println!("Error removing services: {}", e),
 /// }
 /// ```
-fn remove_from_current_selection(user_selection: &mut Vec<String>, services_to_remove: &Vec<String>) -> Result<(), Error> {
+fn remove_from_current_selection(user_selection: &mut Vec<String>, services_to_remove: &[String]) -> Result<(), RyujinError> {
         
     if user_selection.is_empty(){
-        return Err(Error::new(ErrorKind::InvalidInput, "Selection is empty."))
+        return Err(RyujinError::Selection("Selection is empty.".to_string()))
     }
 
     for item in services_to_remove.iter(){
-        match user_selection.contains(item){
+        let matches = |x: &String| {
+            let without_profiles = x.split_once('@').map_or(x.as_str(), |(name, _)| name);
+            let service = without_profiles.split_once(':').map_or(without_profiles, |(service, _)| service);
+            x == item || (!item.contains('@') && (without_profiles == item || (!item.contains(':') && service == item)))
+        };
+        match user_selection.iter().any(matches){
             true => {
-                user_selection.retain(|x| x != item);
+                user_selection.retain(|x| !matches(x));
                 println!("The item {} has been removed.", item)
             },
             false => println!("The item {} is not in the current selection.",item)
@@ -171,10 +185,10 @@ fn remove_from_current_selection(user_selection: &mut Vec<String>, services_to_r
 ///     Err(e) => println!("Error printing selection: {}", e),
 /// }
 /// ```
-fn print_current_selection(user_selection: &Vec<String>) -> Result<(), Error> {
+fn print_current_selection(user_selection: &[String]) -> Result<(), RyujinError> {
     
     match user_selection.is_empty(){
-        true => return Err(Error::new(ErrorKind::InvalidInput, "Selection is empty.")),
+        true => return Err(RyujinError::Selection("Selection is empty.".to_string())),
         false => {
             println!("Current selection:");
             for item in user_selection.iter(){
@@ -186,29 +200,22 @@ fn print_current_selection(user_selection: &Vec<String>) -> Result<(), Error> {
     Ok(())
 }
 
-pub fn handle(mut user_selection: &mut Vec<String>, select_matches: &ArgMatches, catalog : &Services) -> Result<(), Error> {
+pub fn handle(user_selection: &mut Vec<String>, select_matches: &ArgMatches, catalog : &Services) -> Result<(), RyujinError> {
     
     if let Some(true) = select_matches.get_one::<bool>("new") { 
         if let Some(servicess) = select_matches.get_many::<String>("services"){ 
              let new_services: Vec<String> = servicess.map(|s| s.to_string().to_lowercase()).collect();
-             if let Err(e) = select_check_catalog(&new_services, &catalog){
-                //  println!("Error : {} \n", e);
-                //  std::process::exit(1)
-                return Err(e)
-             }
+             select_check_catalog(&new_services, catalog)?;
              
              user_selection.clear();
              println!("New selection created!");
 
-             if let Err(e) = add_to_current_selection(&mut user_selection, &new_services){
-                // println!("Error : {}", e)
-                return Err(e)
-             }
+             add_to_current_selection(user_selection, &new_services)?;
               // peut être remplacer le match par add_to_current_selection(&mut user_selection, &services)
              //     //.unwrap_or_else(|err| println!("Une erreur est survenue: {}", err));
          
          } else {
-             match erase_current_selection(&mut user_selection){
+             match erase_current_selection(user_selection){
                  Ok(_) => println!("New selection created!"),
                  //Err(_) => println!("Selection is already empty.") 
                  Err(e) => return Err(e)
@@ -220,16 +227,9 @@ pub fn handle(mut user_selection: &mut Vec<String>, select_matches: &ArgMatches,
      if let Some(true) = select_matches.get_one::<bool>("add"){
          if let Some(servicess) = select_matches.get_many::<String>("services"){
              let new_services: Vec<String> = servicess.map(|s| s.to_string().to_lowercase()).collect();//gérer le cas ou l'élément est deja dans la liste
-             if let Err(e) = select_check_catalog(&new_services, &catalog){
-                //  println!("Error : {} \n ", e);
-                //  std::process::exit(1)
-                return Err(e)
-             }
+             select_check_catalog(&new_services, catalog)?;
              
-             if let Err(e) = add_to_current_selection(&mut user_selection, &new_services){
-                //  println!("Error : {}", e)
-                return Err(e)
-             }
+             add_to_current_selection(user_selection, &new_services)?;
 
          }
      
@@ -237,7 +237,7 @@ pub fn handle(mut user_selection: &mut Vec<String>, select_matches: &ArgMatches,
      }
 
      if let Some(true) = select_matches.get_one::<bool>("delete"){
-         match erase_current_selection(&mut user_selection){
+         match erase_current_selection(user_selection){
              Ok(_) => println!("Selection deleted!"),
              Err(e) => {
                 //  println!("Error : {}. Selection was not updated.", e);
@@ -253,20 +253,12 @@ pub fn handle(mut user_selection: &mut Vec<String>, select_matches: &ArgMatches,
          if let Some(services) = select_matches.get_many::<String>("services"){
              let services: Vec<String> = services.map(|s| s.to_string().to_lowercase()).collect();
              
-             if let Err(e) = remove_from_current_selection(&mut user_selection, &services){
-                //  println!("Error : {}", e);
-                //  std::process::exit(1)
-                return Err(e)
-             }
+             remove_from_current_selection(user_selection, &services)?;
          }
      }
 
      if let Some(true) = select_matches.get_one::<bool>("print"){ 
-         if let Err(e) = print_current_selection(&user_selection) {
-            //  println!("{}", e);
-            //  std::process::exit(1)
-            return Err(e)
-         }
+         print_current_selection(user_selection)?;
      
      // prendre en compte la casse car actuelement ça ne fonctionne ap 
      //revoir certains retour d'erreur dans mes fonctions et changer des print pour Err(e) => println!("Error : {}" : e)
@@ -275,4 +267,40 @@ pub fn handle(mut user_selection: &mut Vec<String>, select_matches: &ArgMatches,
      }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_to_string;
+
+    fn catalog() -> Services {
+        let path = format!("{}/services/services.json", env!("CARGO_MANIFEST_DIR"));
+        serde_json::from_str(&read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn a_service_is_removed_with_its_instances_and_profiles() {
+        let selection = ["nginx", "nginx:site-a", "nginx:site-b@web", "portainer@admin", "pwn"].map(String::from).to_vec();
+        let removed = |services: &[&str]| {
+            let mut selection = selection.clone();
+            remove_from_current_selection(&mut selection, &services.iter().map(|service| service.to_string()).collect::<Vec<String>>()).unwrap();
+            selection
+        };
+
+        assert_eq!(removed(&["nginx"]), vec!["portainer@admin", "pwn"]);
+        assert_eq!(removed(&["nginx:site-b"]), vec!["nginx", "nginx:site-a", "portainer@admin", "pwn"]);
+        assert_eq!(removed(&["nginx:site-b@admin", "portainer"]), vec!["nginx", "nginx:site-a", "nginx:site-b@web", "pwn"]);
+        assert_eq!(removed(&["site-a", "portainer@other"]), selection);
+    }
+
+    #[test]
+    fn an_unknown_service_is_an_input_error() {
+        let catalog = catalog();
+        assert!(select_check_catalog(&["nginx:site-a@web".to_string(), "portainer".to_string()], &catalog).is_ok());
+
+        let error = select_check_catalog(&["nginx".to_string(), "ngnix:site-a".to_string()], &catalog).unwrap_err();
+        assert!(matches!(error, RyujinError::Input(_)), "{}", error);
+        assert_eq!(error.exit_code(), 2);
+    }
+}
@@ -1,9 +1,9 @@
-use crate::generic::Service;
+use crate::generic::{Service, Size};
 
 /// Prints the details of a specific service.
 ///
 /// This function takes a reference to a `Service` object and prints its details, including the name, description, current version,
-/// modification status, last update, developers, links, and tags. The description is formatted to a width of 80 characters.
+/// modification status, last update, developers, links, tags, networks, the files that can be mounted as Docker secrets, the ports it publishes on the host, its healthcheck, the services it depends on, its recommended resource limits and how the security profiles apply to it. The description is formatted to a width of 80 characters.
 ///
 /// # Arguments
 ///
@@ -23,7 +23,7 @@ pub fn print_service_page(service: &Service) {
     println!("Description:");
     let description = format!("{: <80}", service.description);
     for line in description.as_str().chars().collect::<Vec<char>>().chunks(80) {
-        let line: String = line.into_iter().collect();
+        let line: String = line.iter().collect();
         println!("    {}", line);
     }
     println!("\nCurrent Version: {}", service.current_version);
@@ -35,6 +35,75 @@ pub fn print_service_page(service: &Service) {
         println!("- {}: {}", key, value);
     }
     println!("\nTags: {}", service.tags.join(", "));
+    if !service.networks.is_empty() {
+        println!("Networks: {}", service.networks.join(", "));
+    }
+    if !service.requires.is_empty() {
+        println!("Requires: {}", service.requires.join(", "));
+    }
+    if !service.optional_after.is_empty() {
+        println!("Starts after (when selected): {}", service.optional_after.join(", "));
+    }
+    if !service.secret_files.is_empty() {
+        println!("\nSecret files (--docker-secrets):");
+        for secret_file in &service.secret_files {
+            println!("- {} -> {}", secret_file.variable, secret_file.target);
+        }
+    }
+    if let Some(tls) = &service.tls {
+        println!("\nTLS (generated with --self-signed when left empty): {} and {}", tls.certificate, tls.key);
+    }
+    if !service.ports.is_empty() {
+        println!("\nPorts:");
+        for mapping in &service.ports {
+            let host = match (&mapping.host, &mapping.variable) {
+                (Some(host), _) => host.to_string(),
+                (None, Some(variable)) => format!("<{}>", variable),
+                (None, None) => "?".to_string(),
+            };
+            println!("- {}: {} -> {}", mapping.name, host, mapping.container);
+        }
+    }
+    if let Some(healthcheck) = &service.healthcheck {
+        println!("\nHealthcheck: {}", healthcheck.test.join(" "));
+        let settings = [
+            ("interval", healthcheck.interval.clone()),
+            ("timeout", healthcheck.timeout.clone()),
+            ("retries", healthcheck.retries.map(|retries| retries.to_string())),
+            ("start_period", healthcheck.start_period.clone()),
+        ];
+        for (setting, value) in settings {
+            if let Some(value) = value {
+                println!("- {}: {}", setting, value);
+            }
+        }
+    }
+    let security = &service.security;
+    println!("\nSecurity (--security):");
+    if !security.cap_add.is_empty() {
+        println!("- capabilities kept: {}", security.cap_add.join(", "));
+    }
+    if !security.tmpfs.is_empty() {
+        println!("- writable directories (strict): {}", security.tmpfs.join(", "));
+    }
+    if let Some(user) = &security.user {
+        println!("- user (strict): {}", user);
+    }
+    if !security.opt_out.is_empty() {
+        let opt_out: Vec<String> = security.opt_out.iter().map(|option| option.to_string()).collect();
+        println!("- not supported: {}", opt_out.join(", "));
+    }
+    if !service.resources.is_empty() {
+        println!("\nResource limits (--size):");
+        for size in [Size::Small, Size::Medium, Size::Large] {
+            if let Some(limits) = service.resources.get(&size) {
+                let cpus = limits.cpus.clone().unwrap_or("-".to_string());
+                let memory = limits.memory.clone().unwrap_or("-".to_string());
+                let pids = limits.pids.map(|pids| pids.to_string()).unwrap_or("-".to_string());
+                println!("- {}: {} CPUs, {} memory, {} processes", size, cpus, memory, pids);
+            }
+        }
+    }
     println!("————————————————————————————————————————");
 }
 
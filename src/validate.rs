@@ -0,0 +1,265 @@
+use crate::error::RyujinError;
+use crate::lint::Diagnostic;
+use jsonschema::paths::LocationSegment;
+use jsonschema::error::ValidationErrorKind;
+use regex::Regex;
+use std::fs::read_to_string;
+use std::path::Path;
+
+
+/// The Compose specification JSON schema, bundled in the binary so the validation works offline.
+const COMPOSE_SCHEMA: &str = include_str!("../schema/compose-spec.json");
+
+/// `YamlLine` is a struct that represents a meaningful line of a YAML document, used to find the line of a value.
+///
+/// A sequence item with an inline value (`- key: value`) is split in two entries: the `-` and the value, two columns further.
+struct YamlLine<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+    item: bool,
+}
+
+/// Splits a YAML document into `YamlLine` entries, skipping blank lines and comments.
+fn yaml_lines(content: &str) -> Vec<YamlLine<'_>> {
+    let mut entries: Vec<YamlLine> = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let text = line.trim_start();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - text.len();
+        if text == "-" || text.starts_with("- ") {
+            entries.push(YamlLine { number: index + 1, indent, text: "-", item: true });
+            let rest = text[1..].trim_start();
+            if !rest.is_empty() {
+                entries.push(YamlLine { number: index + 1, indent: indent + text.len() - rest.len(), text: rest, item: false });
+            }
+        } else {
+            entries.push(YamlLine { number: index + 1, indent, text, item: false });
+        }
+    }
+    entries
+}
+
+/// Returns the line of the value at `path` in a YAML document, by walking the indentation.
+///
+/// When the path cannot be followed to the end, the line of the deepest value found is returned.
+///
+/// # Arguments
+///
+/// * `content` - The YAML document.
+/// * `path` - The keys and sequence indexes leading to the value, e.g. `["services", "apache", "ports", "0"]`.
+pub fn line_of(content: &str, path: &[String]) -> usize {
+    let entries = yaml_lines(content);
+    let mut line = 1;
+    // The parent is the entry whose block is searched: its position, its indent and whether it is a sequence item
+    let mut parent: Option<(usize, usize, bool)> = None;
+
+    for segment in path {
+        let block: Vec<(usize, &YamlLine)> = match parent {
+            None => entries.iter().enumerate().collect(),
+            Some((position, indent, is_item)) => entries.iter().enumerate()
+                .skip(position + 1)
+                .take_while(|(_, entry)| entry.indent > indent || (!is_item && entry.indent == indent && entry.item))
+                .collect(),
+        };
+
+        let found = match segment.parse::<usize>() {
+            Ok(index) => {
+                let item_indent = block.iter().find(|(_, entry)| entry.item).map(|(_, entry)| entry.indent);
+                block.iter()
+                    .filter(|(_, entry)| entry.item && Some(entry.indent) == item_indent)
+                    .nth(index)
+                    .copied()
+            },
+            Err(_) => {
+                let key_indent = block.first().map(|(_, entry)| entry.indent);
+                block.iter()
+                    .filter(|(_, entry)| !entry.item && Some(entry.indent) == key_indent)
+                    .find(|(_, entry)| {
+                        let key = entry.text.split_once(':').map(|(key, _)| key.trim().trim_matches(|c| c == '"' || c == '\''));
+                        key == Some(segment.as_str())
+                    })
+                    .copied()
+            },
+        };
+
+        match found {
+            Some((position, entry)) => {
+                line = entry.number;
+                parent = Some((position, entry.indent, entry.item));
+            },
+            None => break,
+        }
+    }
+
+    line
+}
+
+/// Returns the line of the second occurrence of a duplicate key in a YAML map.
+///
+/// serde_yaml reports a duplicate key at the location of the map that contains it, so this looks for the key among the
+/// entries of that map, at the same indentation as its first key.
+///
+/// # Arguments
+///
+/// * `content` - The YAML document.
+/// * `line` - The line of the first key of the map, as reported by serde_yaml.
+/// * `column` - The column of the first key of the map, as reported by serde_yaml.
+/// * `key` - The duplicate key.
+fn duplicate_key_line(content: &str, line: usize, column: usize, key: &str) -> Option<usize> {
+    yaml_lines(content).iter()
+        .filter(|entry| entry.number >= line && !entry.item && entry.indent + 1 == column)
+        .filter(|entry| entry.text.split_once(':').map(|(name, _)| name.trim().trim_matches(|c| c == '"' || c == '\'')) == Some(key))
+        .nth(1)
+        .map(|entry| entry.number)
+}
+
+/// Tells whether an image reference is in fact a URL, e.g. a git repository that should be a build context.
+pub fn is_url(image: &str) -> bool {
+    image.contains("://") || image.starts_with("git@") || image.contains(".git#")
+}
+
+/// Validates the content of a `docker-compose.yml` file.
+///
+/// The following checks are run:
+/// * the content is valid YAML without duplicate keys (e.g. two services with the same name);
+/// * the content matches the bundled Compose specification JSON schema (unknown keys, wrong types...);
+/// * no `image` is a URL, since a git repository must be given as a `build` context;
+/// * every service named in a `depends_on` is declared in the file.
+///
+/// # Arguments
+///
+/// * `content` - The content of the compose file.
+/// * `file_name` - The name of the file, used in the location of the diagnostics.
+///
+/// # Returns
+///
+/// A `Vec<Diagnostic>` with every problem found, sorted by line. The location of a diagnostic is `<file_name>:<line>`.
+///
+/// # Example
+///
+/// ```
+/// let diagnostics = validate_compose("services:\n  web:\n    images: nginx\n", "docker-compose.yml");
+/// ```
+pub fn validate_compose(content: &str, file_name: &str) -> Vec<Diagnostic> {
+    let mut problems: Vec<(usize, String)> = Vec::new();
+
+    // serde_yaml rejects duplicate keys, so two services with the same name are reported here
+    let document: serde_yaml::Value = match serde_yaml::from_str(content) {
+        Ok(document) => document,
+        Err(e) => {
+            let mut line = e.location().map(|location| location.line()).unwrap_or(1);
+            let duplicate = Regex::new(r#"duplicate entry with key "([^"]*)""#).expect("The duplicate key pattern is valid");
+            if let (Some(captures), Some(location)) = (duplicate.captures(&e.to_string()), e.location()) {
+                line = duplicate_key_line(content, location.line(), location.column(), &captures[1]).unwrap_or(line);
+            }
+            return vec![Diagnostic { location: format!("{}:{}", file_name, line), message: e.to_string() }];
+        },
+    };
+    let instance = match serde_json::to_value(&document) {
+        Ok(instance) => instance,
+        Err(e) => return vec![Diagnostic { location: format!("{}:1", file_name), message: e.to_string() }],
+    };
+
+    let schema: serde_json::Value = serde_json::from_str(COMPOSE_SCHEMA).expect("The bundled compose schema is valid JSON");
+    let validator = jsonschema::validator_for(&schema).expect("The bundled compose schema is a valid JSON schema");
+
+    for error in validator.iter_errors(&instance) {
+        let mut path: Vec<String> = error.instance_path().iter()
+            .map(|segment| match segment {
+                LocationSegment::Property(property) => property.to_string(),
+                LocationSegment::Index(index) => index.to_string(),
+            })
+            .collect();
+        // Point at the unexpected key rather than at the object that contains it
+        if let ValidationErrorKind::AdditionalProperties { unexpected } = error.kind() {
+            if let Some(key) = unexpected.first() {
+                path.push(key.clone());
+            }
+        }
+        let location = if path.is_empty() { "the root".to_string() } else { path.join(".") };
+        problems.push((line_of(content, &path), format!("{}: {}", location, error)));
+    }
+
+    if let Some(services) = instance.get("services").and_then(|services| services.as_object()) {
+        for (name, service) in services {
+            if let Some(image) = service.get("image").and_then(|image| image.as_str()) {
+                if is_url(image) {
+                    let path = vec!["services".to_string(), name.clone(), "image".to_string()];
+                    problems.push((line_of(content, &path), format!("services.{}.image: {} is a URL, not an image reference. Use build.context to build from a repository.", name, image)));
+                }
+            }
+
+            // The dependencies with the segment of their path, an index in the short syntax and a key in the long one
+            let dependencies: Vec<(String, String)> = match service.get("depends_on") {
+                Some(serde_json::Value::Array(names)) => names.iter().enumerate()
+                    .filter_map(|(index, name)| name.as_str().map(|name| (name.to_string(), index.to_string())))
+                    .collect(),
+                Some(serde_json::Value::Object(names)) => names.keys().map(|name| (name.clone(), name.clone())).collect(),
+                _ => Vec::new(),
+            };
+            for (dependency, segment) in dependencies.into_iter().filter(|(dependency, _)| !services.contains_key(dependency)) {
+                let path = vec!["services".to_string(), name.clone(), "depends_on".to_string(), segment];
+                problems.push((line_of(content, &path), format!("services.{}.depends_on: {} is not a service of the file.", name, dependency)));
+            }
+        }
+    }
+
+    problems.sort();
+    problems.into_iter()
+        .map(|(line, message)| Diagnostic { location: format!("{}:{}", file_name, line), message })
+        .collect()
+}
+
+/// Validates the `docker-compose.yml` file of a directory and prints the problems found.
+///
+/// # Arguments
+///
+/// * `path` - The directory that contains the `docker-compose.yml` file, or the path of the file itself.
+///
+/// # Errors
+///
+/// This function will return a `RyujinError::Io` if the file cannot be read, and a `RyujinError::Findings` if at least
+/// one problem was found.
+///
+/// # Example
+///
+/// ```
+/// validate::handle("./output").unwrap();
+/// ```
+pub fn handle(path: &str) -> Result<(), RyujinError> {
+    let path = Path::new(path);
+    let file_path = if path.is_dir() { path.join("docker-compose.yml") } else { path.to_path_buf() };
+    let content = read_to_string(&file_path)
+        .map_err(|e| RyujinError::Io(std::io::Error::new(e.kind(), format!("Unable to read {}: {}", file_path.display(), e))))?;
+
+    let file_name = file_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let diagnostics = validate_compose(&content, &file_name);
+    for diagnostic in &diagnostics {
+        println!("error: {}: {}", diagnostic.location, diagnostic.message);
+    }
+
+    if diagnostics.is_empty() {
+        println!("{} is valid.", file_path.display());
+        Ok(())
+    } else {
+        Err(RyujinError::Findings(format!("{} problem(s) found in {}.", diagnostics.len(), file_path.display())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_a_dependency_that_is_not_declared() {
+        let content = "services:\n  web:\n    image: nginx\n    depends_on:\n      - db\n      - cache\n  cache:\n    image: redis\n";
+        let diagnostics = validate_compose(content, "docker-compose.yml");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].location, "docker-compose.yml:5");
+        assert_eq!(diagnostics[0].message, "services.web.depends_on: db is not a service of the file.");
+    }
+}
@@ -0,0 +1,336 @@
+use crate::generic::{Question, QuestionKind};
+use regex::Regex;
+use std::env;
+use std::path::{Path, PathBuf};
+
+
+/// Resolves a host path the way Docker Compose does.
+///
+/// A leading `~` is replaced by the home directory of the user, and relative paths are resolved
+/// against `base_dir`, the directory where the `docker-compose.yml` file is generated.
+///
+/// # Arguments
+///
+/// * `path` - The host path typed by the user.
+/// * `base_dir` - The directory the relative paths are relative to.
+///
+/// # Example
+///
+/// ```
+/// let path = resolve_host_path("./www", Path::new("/home/user/project"));
+/// ```
+pub fn resolve_host_path(path: &str, base_dir: &Path) -> PathBuf {
+    let home = env::var("HOME").unwrap_or_default();
+    if path == "~" {
+        PathBuf::from(home)
+    } else if let Some(rest) = path.strip_prefix("~/") {
+        Path::new(&home).join(rest)
+    } else {
+        base_dir.join(path)
+    }
+}
+
+/// Checks that a number is inside the `min`/`max` bounds of a question.
+fn check_range(value: i64, question: &Question) -> Result<(), String> {
+    if let Some(min) = question.min {
+        if value < min {
+            return Err(format!("The value must be greater than or equal to {}.", min));
+        }
+    }
+    if let Some(max) = question.max {
+        if value > max {
+            return Err(format!("The value must be less than or equal to {}.", max));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the length of a text is inside the `min`/`max` bounds of a question.
+fn check_length(answer: &str, question: &Question) -> Result<(), String> {
+    let length = answer.chars().count() as i64;
+    if let Some(min) = question.min {
+        if length < min {
+            return Err(format!("The answer must contain at least {} characters.", min));
+        }
+    }
+    if let Some(max) = question.max {
+        if length > max {
+            return Err(format!("The answer must contain at most {} characters.", max));
+        }
+    }
+    Ok(())
+}
+
+/// Validates a port number between 1 and 65535.
+fn validate_port(answer: &str, question: &Question) -> Result<String, String> {
+    let port: u16 = answer.parse()
+        .map_err(|_| format!("{} is not a valid port. A port is a number between 1 and 65535.", answer))?;
+    if port == 0 {
+        return Err("The port 0 cannot be published. A port is a number between 1 and 65535.".to_string());
+    }
+    check_range(port as i64, question)?;
+    Ok(port.to_string())
+}
+
+/// Validates an integer.
+fn validate_int(answer: &str, question: &Question) -> Result<String, String> {
+    let value: i64 = answer.parse()
+        .map_err(|_| format!("{} is not a valid integer.", answer))?;
+    check_range(value, question)?;
+    Ok(value.to_string())
+}
+
+/// Validates a yes/no answer and normalizes it to `true` or `false`.
+fn validate_bool(answer: &str) -> Result<String, String> {
+    match answer.to_lowercase().as_str() {
+        "y" | "yes" | "true" | "1" => Ok("true".to_string()),
+        "n" | "no" | "false" | "0" => Ok("false".to_string()),
+        _ => Err(format!("{} is not a valid answer. Answer with y or n.", answer)),
+    }
+}
+
+/// Validates that the answer is one of the choices of the question.
+fn validate_enum(answer: &str, question: &Question) -> Result<String, String> {
+    match question.choices.iter().find(|choice| choice.eq_ignore_ascii_case(answer)) {
+        Some(choice) => Ok(choice.clone()),
+        None => Err(format!("{} is not a valid choice. Choose one of: {}.", answer, question.choices.join(", "))),
+    }
+}
+
+/// Validates a Docker container name.
+///
+/// Docker only accepts names that start with a letter or a digit, followed by letters, digits, `_`, `.` or `-`.
+fn validate_container_name(answer: &str, question: &Question) -> Result<String, String> {
+    let container_name = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9_.-]*$").expect("The container name pattern is valid");
+    if !container_name.is_match(answer) {
+        return Err(format!("{} is not a valid container name. It must start with a letter or a digit and only contain letters, digits, '_', '.' or '-'.", answer));
+    }
+    check_length(answer, question)?;
+    Ok(answer.to_string())
+}
+
+/// Validates a host path, and checks that it exists when the question expects a file or a directory.
+///
+/// The path is written in a bind mount, `<host path>:<path in the container>`, so it cannot contain `:`.
+fn validate_path(answer: &str, question: &Question, base_dir: &Path) -> Result<String, String> {
+    if answer.chars().any(|c| c.is_control()) {
+        return Err("A path cannot contain control characters.".to_string());
+    }
+    // `:` separates the host path from the path in the container in a bind mount
+    if answer.contains(':') {
+        return Err("A path cannot contain ':', which separates the host path from the path in the container.".to_string());
+    }
+
+    let path = resolve_host_path(answer, base_dir);
+    match question.kind {
+        QuestionKind::File if !path.is_file() => Err(format!("{} is not an existing file (resolved to {}).", answer, path.display())),
+        QuestionKind::Dir if !path.is_dir() => Err(format!("{} is not an existing directory (resolved to {}).", answer, path.display())),
+        _ => Ok(answer.to_string()),
+    }
+}
+
+/// Tells whether the answer of a `volume` question is the name of a named volume rather than a host path.
+///
+/// Like Docker Compose, a source that starts with `/`, `.` or `~` is a host path, anything else is a volume name.
+///
+/// # Example
+///
+/// ```
+/// assert!(is_named_volume("portainer_data"));
+/// assert!(!is_named_volume("./data"));
+/// ```
+pub fn is_named_volume(answer: &str) -> bool {
+    !(answer.starts_with('/') || answer.starts_with('.') || answer.starts_with('~'))
+}
+
+/// Validates the answer of a `volume` question: a host path, or the name of a named volume.
+fn validate_volume(answer: &str, question: &Question, base_dir: &Path) -> Result<String, String> {
+    if !is_named_volume(answer) {
+        return validate_path(answer, question, base_dir);
+    }
+
+    let volume_name = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9_.-]+$").expect("The volume name pattern is valid");
+    if !volume_name.is_match(answer) {
+        return Err(format!("{} is not a valid volume name. Start a host path with /, ./ or ~/, or give a volume name of at least 2 characters made of letters, digits, '_', '.' or '-'.", answer));
+    }
+    Ok(answer.to_string())
+}
+
+/// Validates an answer against the kind and the constraints of a question.
+///
+/// The answer is trimmed and then checked by the validator of the question's kind. If the question has a `regex`,
+/// the answer must also match it.
+///
+/// # Arguments
+///
+/// * `question` - A reference to the `Question` the answer belongs to.
+/// * `answer` - The answer to validate.
+/// * `base_dir` - The directory relative host paths are resolved against.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The normalized answer (for instance `true`/`false` for a `bool` question).
+/// * `Err(String)` - An explanation of why the answer is not valid, meant to be shown to the user.
+///
+/// # Example
+///
+/// ```
+/// let answer = validate_answer(&question, "8080", Path::new(".")).unwrap();
+/// ```
+pub fn validate_answer(question: &Question, answer: &str, base_dir: &Path) -> Result<String, String> {
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Err("An answer is required.".to_string());
+    }
+
+    let normalized = match question.kind {
+        QuestionKind::Port => validate_port(answer, question)?,
+        QuestionKind::Int => validate_int(answer, question)?,
+        QuestionKind::Bool => validate_bool(answer)?,
+        QuestionKind::Enum => validate_enum(answer, question)?,
+        QuestionKind::ContainerName => validate_container_name(answer, question)?,
+        QuestionKind::Volume => validate_volume(answer, question, base_dir)?,
+        QuestionKind::HostPath | QuestionKind::File | QuestionKind::Dir => validate_path(answer, question, base_dir)?,
+        QuestionKind::Text => {
+            check_length(answer, question)?;
+            answer.to_string()
+        },
+    };
+
+    if let Some(pattern) = &question.regex {
+        let regex = Regex::new(pattern)
+            .map_err(|e| format!("The pattern {} of the question {} is invalid: {}", pattern, question.variable, e))?;
+        if !regex.is_match(&normalized) {
+            return Err(format!("{} does not match the expected format {}.", normalized, pattern));
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// Describes the answer expected by a question, to be displayed next to the question.
+///
+/// # Arguments
+///
+/// * `question` - A reference to the `Question` to describe.
+///
+/// # Example
+///
+/// ```
+/// println!("{} {}", question.question, describe_expected(&question));
+/// ```
+pub fn describe_expected(question: &Question) -> String {
+    match question.kind {
+        QuestionKind::Port => "[port]".to_string(),
+        QuestionKind::HostPath => "[host path]".to_string(),
+        QuestionKind::File => "[existing file]".to_string(),
+        QuestionKind::Dir => "[existing directory]".to_string(),
+        QuestionKind::ContainerName => "[container name]".to_string(),
+        QuestionKind::Volume => "[host path or volume name]".to_string(),
+        QuestionKind::Enum => format!("[{}]", question.choices.join("/")),
+        QuestionKind::Bool => "[y/n]".to_string(),
+        QuestionKind::Int => "[integer]".to_string(),
+        QuestionKind::Text => "[text]".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    /// Creates a question of a kind, with the given constraints, e.g. `{ "min": 1 }`.
+    fn question(kind: &str, constraints: serde_json::Value) -> Question {
+        let mut question = serde_json::json!({ "question": "", "variable": "answer", "kind": kind, "answer": null });
+        question.as_object_mut().unwrap().extend(constraints.as_object().unwrap().clone());
+        serde_json::from_value(question).unwrap()
+    }
+
+    /// Checks the answers accepted by a question, with their normalized form, and the answers rejected.
+    fn check(question: &Question, accepted: &[(&str, &str)], rejected: &[&str]) {
+        for (answer, normalized) in accepted {
+            assert_eq!(validate_answer(question, answer, Path::new("/nonexistent")).as_deref(), Ok(*normalized), "{:?} {}", question.kind, answer);
+        }
+        for answer in rejected {
+            assert!(validate_answer(question, answer, Path::new("/nonexistent")).is_err(), "{:?} {} is accepted", question.kind, answer);
+        }
+    }
+
+    #[test]
+    fn ports() {
+        check(&question("port", serde_json::json!({})), &[("80", "80"), (" 8080 ", "8080"), ("65535", "65535")], &["0", "65536", "-1", "http", ""]);
+        check(&question("port", serde_json::json!({ "min": 1024 })), &[("1024", "1024")], &["80"]);
+    }
+
+    #[test]
+    fn integers() {
+        check(&question("int", serde_json::json!({ "min": -5, "max": 10 })), &[("-5", "-5"), ("+7", "7"), ("10", "10")], &["-6", "11", "1.5", "ten"]);
+    }
+
+    #[test]
+    fn booleans() {
+        check(&question("bool", serde_json::json!({})), &[("y", "true"), ("YES", "true"), ("1", "true"), ("n", "false"), ("False", "false"), ("0", "false")], &["maybe", "oui"]);
+    }
+
+    #[test]
+    fn choices() {
+        check(&question("enum", serde_json::json!({ "choices": ["npm", "yarn"] })), &[("npm", "npm"), ("YARN", "yarn")], &["pnpm", "npm yarn"]);
+    }
+
+    #[test]
+    fn container_names() {
+        check(&question("container_name", serde_json::json!({ "max": 10 })), &[("web", "web"), ("My.web_1-a", "My.web_1-a")], &["-web", "_web", "my web", "web/1", "a-very-long-name"]);
+    }
+
+    #[test]
+    fn texts() {
+        check(&question("text", serde_json::json!({ "min": 3, "regex": "^[a-z]+$" })), &[("pwn", "pwn")], &["ab", "Pwn", "pwn1"]);
+    }
+
+    #[test]
+    fn host_paths() {
+        check(&question("host_path", serde_json::json!({})),
+            &[("/srv/www", "/srv/www"), ("./data", "./data"), ("~/site", "~/site"), ("/srv/my #site", "/srv/my #site")],
+            &["/srv/a:b", "./data:/data", "C:/www", "/srv/\u{7}bell", "/srv/line\nbreak"]);
+    }
+
+    #[test]
+    fn files_and_directories_must_exist() {
+        let dir = std::env::temp_dir().join(format!("ryujin-validator-{}", std::process::id()));
+        create_dir_all(dir.join("www")).unwrap();
+        write(dir.join("server.crt"), "certificate").unwrap();
+        let file = question("file", serde_json::json!({}));
+        let directory = question("dir", serde_json::json!({}));
+
+        let results = [
+            validate_answer(&file, "./server.crt", &dir),
+            validate_answer(&file, "./www", &dir),
+            validate_answer(&file, "./missing.crt", &dir),
+            validate_answer(&directory, "./www", &dir),
+            validate_answer(&directory, "./server.crt", &dir),
+        ];
+        remove_dir_all(&dir).unwrap();
+
+        assert_eq!(results[0].as_deref(), Ok("./server.crt"));
+        assert!(results[1].is_err() && results[2].is_err());
+        assert_eq!(results[3].as_deref(), Ok("./www"));
+        assert!(results[4].is_err());
+    }
+
+    #[test]
+    fn volumes_are_a_host_path_or_a_volume_name() {
+        check(&question("volume", serde_json::json!({})),
+            &[("portainer_data", "portainer_data"), ("/srv/data", "/srv/data"), ("./data", "./data")],
+            &["a", "_data", "my data", "/srv/a:b"]);
+        assert!(is_named_volume("portainer_data"));
+        assert!(!is_named_volume("./data") && !is_named_volume("/data") && !is_named_volume("~/data"));
+    }
+
+    #[test]
+    fn paths_are_resolved_like_compose() {
+        let home = env::var("HOME").unwrap_or_default();
+        assert_eq!(resolve_host_path("./www", Path::new("/project")), Path::new("/project/www"));
+        assert_eq!(resolve_host_path("/srv", Path::new("/project")), Path::new("/srv"));
+        assert_eq!(resolve_host_path("~/www", Path::new("/project")), Path::new(&home).join("www"));
+    }
+}
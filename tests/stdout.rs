@@ -0,0 +1,84 @@
+use std::fs::{create_dir_all, read_to_string, remove_dir_all};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+
+/// Runs `compose --stdout` in a pseudo-terminal, so the questions are asked, with the standard output redirected to a file.
+///
+/// `script` gives the command a terminal; it is only available with these options on Linux.
+#[cfg(target_os = "linux")]
+#[test]
+fn stdout_only_holds_the_docker_compose() {
+    if Command::new("script").arg("--version").output().is_err() {
+        eprintln!("script is not installed, the test is skipped");
+        return;
+    }
+
+    let dir = std::env::temp_dir().join(format!("ryujin-stdout-{}", std::process::id()));
+    create_dir_all(&dir).unwrap();
+    let command = format!("{} compose -s portainer --stdout -o out > out.yml", env!("CARGO_BIN_EXE_ryujin-cli"));
+
+    let mut child = Command::new("script")
+        .args(["-qec", &command, "/dev/null"])
+        .current_dir(&dir)
+        .env("RYUJIN_CLI_PATH", env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Every question is answered with its default
+    child.stdin.take().unwrap().write_all("\n".repeat(10).as_bytes()).unwrap();
+    let result = child.wait_with_output().unwrap();
+    let terminal = String::from_utf8_lossy(&result.stdout).to_string();
+
+    let compose = read_to_string(dir.join("out.yml")).unwrap();
+    let output_dir_created = dir.join("out").exists();
+    remove_dir_all(&dir).unwrap();
+
+    assert!(result.status.success(), "compose failed:\n{}", terminal);
+    assert!(terminal.contains("Enter the name of the Portainer container"), "the questions were not asked:\n{}", terminal);
+    assert!(compose.starts_with("services:"), "the standard output holds more than the docker-compose:\n{}", compose);
+    let document: serde_yaml::Value = serde_yaml::from_str(&compose).unwrap();
+    assert!(document["services"]["portainer"].is_mapping());
+    assert!(!output_dir_created, "the output directory was created");
+}
+
+/// Runs `compose --stdout` with an answers file and no terminal, and returns its exit code, standard output and error.
+fn compose(args: &[&str], answers: &str) -> (Option<i32>, String, String) {
+    let dir = std::env::temp_dir().join(format!("ryujin-stdout-{}-{}", std::process::id(), args.join("-").replace(['/', '=', '.'], "_")));
+    create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("answers.yaml"), answers).unwrap();
+
+    let result = Command::new(env!("CARGO_BIN_EXE_ryujin-cli"))
+        .args(["compose", "--answers", "answers.yaml", "--stdout", "-o", "out"])
+        .args(args)
+        .current_dir(&dir)
+        .env("RYUJIN_CLI_PATH", env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    remove_dir_all(&dir).unwrap();
+
+    (result.status.code(), String::from_utf8_lossy(&result.stdout).to_string(), String::from_utf8_lossy(&result.stderr).to_string())
+}
+
+#[test]
+fn a_limit_beats_the_size_preset() {
+    let (code, compose, errors) = compose(&["-s", "pwn", "--size", "small", "--limit", "pwn.memory=2g"], "pwn_files: ./files\n");
+
+    assert_eq!(code, Some(0), "compose failed:\n{}", errors);
+    let document: serde_yaml::Value = serde_yaml::from_str(&compose).unwrap();
+    let limits = &document["services"]["pwnbox"]["deploy"]["resources"]["limits"];
+    assert_eq!(limits["memory"].as_str(), Some("2g"));
+    assert_eq!(limits["cpus"].as_str(), Some("0.5"));
+    assert_eq!(limits["pids"].as_u64(), Some(64));
+}
+
+#[test]
+fn an_invalid_size_or_limit_is_an_input_error() {
+    for args in [["--size", "huge"], ["--limit", "pwn.memory=1t"], ["--limit", "nginx.memory=1g"]] {
+        let (code, _, errors) = compose(&[&["-s", "pwn"], &args[..]].concat(), "pwn_files: ./files\n");
+        assert_eq!(code, Some(2), "{} was accepted:\n{}", args.join(" "), errors);
+    }
+}